@@ -0,0 +1,582 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::mem;
+use std::rc::Rc;
+use termion::event::Key;
+use unicode_segmentation::UnicodeSegmentation;
+use encoding;
+use error::Result;
+use gutter::LineNumbers;
+use history::{Edit, History};
+use swap::Swap;
+use syntax::{HlState, Syntax, SyntaxRe};
+use text::{Format, Position, Text};
+use window::View;
+use wrap::KILO_SHOWBREAK;
+use {grapheme_width, Row, KILO_SOFT_TAB, KILO_TAB_STOP};
+
+#[derive(Copy, Clone, PartialEq)]
+enum CharClass {
+    Blank,
+    Punctuation,
+    Word,
+}
+
+// Everything that belongs to one open file.
+pub struct Buffer {
+    pub cx: usize,
+    pub cy: usize,
+    pub rx: usize,
+    pub rowoff: usize,
+    pub coloff: usize,
+    pub text: Text,
+    pub format: Format,
+    saved_format: Format,
+    pub dirty: bool,
+    // Bumped by every edit.
+    pub version: usize,
+    pub swap: Swap,
+    // Set for binary files, saving them needs `:w!`.
+    pub read_only: bool,
+    pub history: History,
+    pub filename: Option<String>,
+    pub tab_stop: usize,
+    pub soft_tab: usize,
+    pub line_numbers: LineNumbers,
+    // Soft wrapping: long rows continue on the next screen lines, broken
+    // at blanks with `linebreak`, and `showbreak` is drawn before each
+    // continuation.
+    pub wrap: bool,
+    pub linebreak: bool,
+    pub showbreak: String,
+    // Text width of the active window, which Up and Down move within.
+    pub wrap_width: usize,
+    pub syntax: Rc<Syntax>,
+    pub syntax_re: Rc<SyntaxRe>,
+    pub syntax_states: Vec<HlState>,
+    // Rendered rows by row number, only kept while they are on screen.
+    rows: HashMap<usize, Row>,
+    // Bumped when every row has to be highlighted again.
+    highlight_version: usize,
+}
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        let syntax = Syntax::new();
+        Buffer {
+            cx: 0,
+            cy: 0,
+            rx: 0,
+            rowoff: 0,
+            coloff: 0,
+            text: Text::new(),
+            format: Format::new(),
+            saved_format: Format::new(),
+            dirty: false,
+            version: 0,
+            swap: Swap::new(),
+            read_only: false,
+            history: History::new(),
+            filename: None,
+            tab_stop: KILO_TAB_STOP,
+            soft_tab: KILO_SOFT_TAB,
+            line_numbers: LineNumbers::Off,
+            wrap: false,
+            linebreak: false,
+            showbreak: KILO_SHOWBREAK.to_string(),
+            wrap_width: 0,
+            syntax_re: Rc::new(SyntaxRe::plain()),
+            syntax: Rc::new(syntax),
+            syntax_states: Vec::new(),
+            rows: HashMap::new(),
+            highlight_version: 0,
+        }
+    }
+
+    // Returns false when the file does not exist yet.
+    pub fn read_file(&mut self, filename: String) -> Result<bool> {
+        let mut file = match File::open(&filename) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                self.filename = Some(filename);
+                return Ok(false)
+            },
+            Err(err) => return Err(err.into()),
+        };
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        self.filename = Some(filename);
+        let decoded = encoding::decode(bytes);
+        self.read_only = decoded.binary;
+        let (text, format) = Text::from_decoded(decoded);
+        self.text = text;
+        self.format = format;
+        self.saved_format = format;
+        self.syntax_states.clear();
+        self.rows.clear();
+        Ok(true)
+    }
+
+    pub fn set_syntax(&mut self, syntax: Rc<Syntax>, syntax_re: Rc<SyntaxRe>) {
+        self.syntax = syntax;
+        self.syntax_re = syntax_re;
+        self.syntax_states.clear();
+        self.highlight_version += 1;
+    }
+
+    // Converting line endings, the final newline or the BOM modifies the
+    // buffer without touching the text.
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+        self.dirty = !self.history.is_saved() || self.format != self.saved_format;
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.history.mark_saved();
+        self.saved_format = self.format;
+        self.dirty = false;
+    }
+
+    // The empty unnamed buffer the editor starts with.
+    pub fn is_scratch(&self) -> bool {
+        self.filename.is_none() && !self.dirty && self.text.is_empty()
+    }
+
+    pub fn view(&self) -> View {
+        View { cx: self.cx, cy: self.cy, rowoff: self.rowoff, coloff: self.coloff }
+    }
+
+    // Takes over the view of a window, which may predate edits made elsewhere.
+    pub fn set_view(&mut self, view: View) {
+        let view = self.clamp_view(view);
+        self.cy = view.cy;
+        self.cx = view.cx;
+        self.rowoff = view.rowoff;
+        self.coloff = view.coloff;
+    }
+
+    // `view` with its cursor and top row moved back into the text, for
+    // views of other windows that have not followed the last edits.
+    pub fn clamp_view(&self, mut view: View) -> View {
+        let rows = self.text.len();
+        if view.cy >= rows {
+            view.cy = rows.saturating_sub(1);
+            view.cx = if rows == 0 {0} else {self.row_len(view.cy).saturating_sub(1)};
+        } else {
+            view.cx = view.cx.min(self.row_len(view.cy));
+        }
+        view.rowoff = view.rowoff.min(view.cy);
+        view
+    }
+
+    // Name shown in the status bar and buffer list.
+    pub fn name(&self) -> String {
+        self.filename.clone().unwrap_or_else(|| "[None]".to_string())
+    }
+
+    pub fn cursor_position(&self) -> Position {
+        (self.cy, self.text.byte_offset(self.cy, self.cx))
+    }
+
+    pub fn set_cursor(&mut self, (y, byte): Position) {
+        self.cy = y;
+        self.cx = self.text.column(y, byte);
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        let at = self.cursor_position();
+        self.insert_text(at, &c.to_string());
+    }
+
+    pub fn delete_char(&mut self) {
+        if self.cy == self.text.len() {return}
+        if self.cx == 0 && self.cy == 0 {return}
+
+        let (cy, end) = self.cursor_position();
+        if self.cx > 0 {
+            let soft_tab = self.soft_tab > 0 && self.text.line(cy).byte_slice(..end).to_string()
+                .ends_with(&" ".repeat(self.soft_tab));
+            let start = if soft_tab {
+                end - self.soft_tab
+            } else {
+                self.text.byte_offset(cy, self.cx - 1)
+            };
+            self.delete_text((cy, start), (cy, end));
+        } else {
+            let previous_len = self.text.line_bytes(cy - 1);
+            self.delete_text((cy - 1, previous_len), (cy, 0));
+        }
+    }
+
+    // `soft_tab` spaces, or a real tab when soft tabs are off.
+    pub fn insert_tab(&mut self) {
+        let at = self.cursor_position();
+        if self.soft_tab == 0 {
+            self.insert_text(at, "\t");
+        } else {
+            self.insert_text(at, &" ".repeat(self.soft_tab));
+        }
+    }
+
+    pub fn insert_newline(&mut self) {
+        let at = self.cursor_position();
+        if self.cy >= self.text.len() && !self.text.is_empty() {
+            self.insert_text(at, "");
+        } else {
+            self.insert_text(at, "\n");
+        }
+    }
+
+    pub fn insert_text(&mut self, at: Position, text: &str) {
+        // Typing on the line past the end of the file first has to create it.
+        let edit = if at.0 >= self.text.len() && !self.text.is_empty() {
+            let last = self.text.len() - 1;
+            Edit::Insert { at: (last, self.text.line_bytes(last)), text: format!("\n{}", text) }
+        } else {
+            Edit::Insert { at, text: text.to_string() }
+        };
+        let before = self.cursor_position();
+        self.apply_edit(&edit);
+        let after = edit.end();
+        self.set_cursor(after);
+        if !edit.text().is_empty() {
+            self.history.record(edit, before, after);
+        }
+    }
+
+    pub fn delete_text(&mut self, start: Position, end: Position) {
+        if start >= end || start.0 >= self.text.len() {return}
+        let end = if end.0 >= self.text.len() {
+            let last = self.text.len() - 1;
+            (last, self.text.line_bytes(last))
+        } else {
+            (end.0, end.1.min(self.text.line_bytes(end.0)))
+        };
+        let before = self.cursor_position();
+        let edit = Edit::Delete { at: start, text: self.text.slice(start, end) };
+        self.apply_edit(&edit);
+        self.set_cursor(start);
+        self.history.record(edit, before, start);
+    }
+
+    fn apply_edit(&mut self, edit: &Edit) {
+        self.dirty = true;
+        self.version += 1;
+        let rows = self.text.len();
+        let first = edit.at().0;
+        let changed = match *edit {
+            Edit::Insert { at, ref text } => {
+                self.text.insert(at, text);
+                if first < rows {1} else {0}
+            },
+            Edit::Delete { at, ref text } => {
+                let end = edit.end();
+                self.text.remove(at, end);
+                text.matches('\n').count() + 1
+            },
+        };
+        let inserted = changed + self.text.len() - rows;
+        self.update_syntax_states(first, changed, inserted);
+        // Cached rows below the edit move with their text.
+        self.rows = mem::take(&mut self.rows).into_iter()
+            .filter_map(|(y, row)| match y {
+                y if y < first => Some((y, row)),
+                y if y >= first + changed => Some((y + inserted - changed, row)),
+                _ => None,
+            })
+            .collect();
+    }
+
+    // Rows `first..first + removed` were replaced by `first..first + inserted`.
+    // Following rows are only re-scanned until one ends in the same state as
+    // before the edit, the rest of the states are left where they are.
+    fn update_syntax_states(&mut self, first: usize, removed: usize, inserted: usize) {
+        if first + removed > self.syntax_states.len() {
+            self.syntax_states.truncate(first);
+            return
+        }
+        let start = self.start_state(first);
+        let old_end = if removed == 0 {start} else {self.syntax_states[first + removed - 1]};
+        let mut state = start;
+        let new: Vec<HlState> = (first..first + inserted).map(|y| {
+            state = self.syntax_re.end_state(&self.text.line(y).to_string(), state);
+            state
+        }).collect();
+        if inserted == removed {
+            self.syntax_states[first..first + inserted].copy_from_slice(&new);
+        } else {
+            self.syntax_states.splice(first..first + removed, new);
+        }
+        if state == old_end {return}
+        for y in first + inserted..self.syntax_states.len() {
+            state = self.syntax_re.end_state(&self.text.line(y).to_string(), state);
+            if state == self.syntax_states[y] {return}
+            self.syntax_states[y] = state;
+        }
+    }
+
+    fn start_state(&self, row: usize) -> HlState {
+        if row == 0 {
+            HlState::Normal
+        } else {
+            self.syntax_states[row - 1]
+        }
+    }
+
+    pub fn syntax_state(&mut self, row: usize) -> HlState {
+        if !self.syntax_re.has_multiline() {
+            return HlState::Normal
+        }
+        while self.syntax_states.len() < row {
+            let y = self.syntax_states.len();
+            let state = self.syntax_re.end_state(&self.text.line(y).to_string(), self.start_state(y));
+            self.syntax_states.push(state);
+        }
+        self.start_state(row)
+    }
+
+    // Row `y` rendered and highlighted. Rows below an edit stay cached and
+    // are only highlighted again if the state they start in has changed.
+    pub fn row(&mut self, y: usize) -> &Row {
+        let state = self.syntax_state(y);
+        let (version, tab_stop) = (self.highlight_version, self.tab_stop);
+        let stale = self.rows.get(&y).is_none_or(|row| row.version != version || row.state != state || row.tab_stop != tab_stop);
+        if stale {
+            let row = Row::new(self.text.line(y).to_string(), Rc::clone(&self.syntax_re), state, tab_stop, version);
+            if self.syntax_states.len() == y && self.syntax_re.has_multiline() {
+                self.syntax_states.push(row.next_state);
+            }
+            self.rows.insert(y, row);
+        }
+        &self.rows[&y]
+    }
+
+    // Forgets rendered rows that are not in `visible`, the first row and
+    // height of each window showing the buffer.
+    pub fn trim_rows(&mut self, visible: &[(usize, usize)]) {
+        self.rows.retain(|&y, _| visible.iter().any(|&(top, height)| y >= top && y < top + height));
+    }
+
+    pub fn replay(&mut self, edits: Vec<Edit>, cursor: Position) {
+        for edit in &edits {
+            self.apply_edit(edit);
+        }
+        self.set_cursor(cursor);
+        self.dirty = !self.history.is_saved() || self.format != self.saved_format;
+    }
+
+    pub fn row_cx_to_rx(&self, row: usize, cx: usize) -> usize {
+        let mut rx = 0;
+        for grapheme in self.text.line(row).to_string().graphemes(true).take(cx) {
+            rx += grapheme_width(grapheme, rx, self.tab_stop);
+        }
+        rx
+    }
+
+    // Index of the grapheme drawn at column `rx`, or the row length past its end.
+    pub fn row_rx_to_cx(&self, row: usize, rx: usize) -> usize {
+        let mut width = 0;
+        let line = self.text.line(row).to_string();
+        for (cx, grapheme) in line.graphemes(true).enumerate() {
+            width += grapheme_width(grapheme, width, self.tab_stop);
+            if width > rx {return cx}
+        }
+        self.text.line_len(row)
+    }
+
+    pub fn move_cursor(&mut self, key: Key) {
+        if self.move_screen_line(key) {return}
+        // let mut rowInput = None;
+        {
+            let row_length = if self.cy >= self.text.len() {
+                0
+            } else {
+                self.text.line_len(self.cy)
+            };
+
+            match key {
+                Key::Down => self.cy += if self.cy < self.text.len() { 1 } else { 0 },
+                Key::Up => self.cy -= if self.cy > 0 { 1 } else { 0 },
+                Key::Right => if row_length > 0 && self.cx < row_length {
+                    self.cx += 1
+                },
+                Key::Left => self.cx -= if self.cx > 0 { 1 } else { 0 },
+                _ => panic!("only call with cursor keys")
+            }
+        }
+
+        if self.cy < self.text.len() {
+            let rowlen = self.text.line_len(self.cy);
+            if self.cx > rowlen{
+                self.cx = rowlen;
+            }
+        };
+    }
+
+    pub fn row_len(&self, at: usize) -> usize {
+        self.text.line_len(at)
+    }
+
+    pub fn first_non_blank(&self, at: usize) -> usize {
+        if at >= self.text.len() {return 0}
+        self.text.line(at).to_string().graphemes(true)
+            .take_while(|g| g.chars().all(char::is_whitespace))
+            .count()
+    }
+
+    pub fn move_to_row(&mut self, at: usize) {
+        self.cy = at;
+        self.cx = self.first_non_blank(at);
+    }
+
+    pub fn clamp_cursor(&mut self) {
+        if self.text.is_empty() {
+            self.cy = 0;
+            self.cx = 0;
+            return
+        }
+        if self.cy >= self.text.len() {
+            self.cy = self.text.len() - 1;
+        }
+        let len = self.row_len(self.cy);
+        if self.cx >= len {
+            self.cx = len.saturating_sub(1);
+        }
+    }
+
+    fn char_class(&self, (y, x): (usize, usize)) -> CharClass {
+        match self.text.grapheme(y, x).and_then(|g| g.chars().next()) {
+            None => CharClass::Blank,
+            Some(c) if c.is_whitespace() => CharClass::Blank,
+            Some(c) if c.is_alphanumeric() || c == '_' => CharClass::Word,
+            Some(_) => CharClass::Punctuation,
+        }
+    }
+
+    fn next_position(&self, (y, x): (usize, usize)) -> Option<(usize, usize)> {
+        if x + 1 < self.row_len(y) {
+            Some((y, x + 1))
+        } else if y + 1 < self.text.len() {
+            Some((y + 1, 0))
+        } else {
+            None
+        }
+    }
+
+    fn previous_position(&self, (y, x): (usize, usize)) -> Option<(usize, usize)> {
+        if x > 0 {
+            Some((y, x.min(self.row_len(y)) - 1))
+        } else if y > 0 {
+            Some((y - 1, self.row_len(y - 1).saturating_sub(1)))
+        } else {
+            None
+        }
+    }
+
+    pub fn word_forward(&mut self) {
+        if self.cy >= self.text.len() {return}
+        let mut pos = (self.cy, self.cx);
+        let start = self.char_class(pos);
+        let mut blank = false;
+        while let Some(next) = self.next_position(pos) {
+            if next.0 != pos.0 {
+                blank = true;
+                if self.row_len(next.0) == 0 {
+                    pos = next;
+                    break
+                }
+            }
+            pos = next;
+            let class = self.char_class(pos);
+            if class == CharClass::Blank {
+                blank = true;
+            } else if blank || class != start {
+                break
+            }
+        }
+        self.cy = pos.0;
+        self.cx = pos.1;
+    }
+
+    pub fn word_end(&mut self) {
+        if self.cy >= self.text.len() {return}
+        let mut pos = match self.next_position((self.cy, self.cx)) {
+            Some(next) => next,
+            None => return,
+        };
+        while self.char_class(pos) == CharClass::Blank {
+            match self.next_position(pos) {
+                Some(next) => pos = next,
+                None => break,
+            }
+        }
+        let class = self.char_class(pos);
+        while let Some(next) = self.next_position(pos) {
+            if next.0 != pos.0 || self.char_class(next) != class {break}
+            pos = next;
+        }
+        self.cy = pos.0;
+        self.cx = pos.1;
+    }
+
+    pub fn word_backward(&mut self) {
+        if self.cy >= self.text.len() {return}
+        let mut pos = match self.previous_position((self.cy, self.cx)) {
+            Some(previous) => previous,
+            None => return,
+        };
+        while self.char_class(pos) == CharClass::Blank && self.row_len(pos.0) > 0 {
+            match self.previous_position(pos) {
+                Some(previous) => pos = previous,
+                None => break,
+            }
+        }
+        let class = self.char_class(pos);
+        if class != CharClass::Blank {
+            while let Some(previous) = self.previous_position(pos) {
+                if previous.0 != pos.0 || self.char_class(previous) != class {break}
+                pos = previous;
+            }
+        }
+        self.cy = pos.0;
+        self.cx = pos.1;
+    }
+
+    pub fn delete_char_under_cursor(&mut self) {
+        if self.cx >= self.row_len(self.cy) {return}
+        let (cy, start) = self.cursor_position();
+        let end = self.text.byte_offset(cy, self.cx + 1);
+        self.delete_text((cy, start), (cy, end));
+    }
+
+    pub fn delete_row(&mut self, at: usize) {
+        self.delete_rows(at, at);
+    }
+
+    // Deletes rows `first..=last` along with their line breaks.
+    pub fn delete_rows(&mut self, first: usize, last: usize) {
+        if first >= self.text.len() {return}
+        let last = last.min(self.text.len() - 1);
+        if last + 1 < self.text.len() {
+            self.delete_text((first, 0), (last + 1, 0));
+        } else if first > 0 {
+            let previous_len = self.text.line_bytes(first - 1);
+            let len = self.text.line_bytes(last);
+            self.delete_text((first - 1, previous_len), (last, len));
+        } else {
+            let len = self.text.line_bytes(last);
+            self.delete_text((first, 0), (last, len));
+        }
+        self.cy = first;
+        self.clamp_cursor();
+        self.cx = self.first_non_blank(self.cy);
+    }
+
+    pub fn delete_range(&mut self, start: (usize, usize), end: (usize, usize)) {
+        let start = (start.0, self.text.byte_offset(start.0, start.1));
+        let end = (end.0, self.text.byte_offset(end.0, end.1 + 1));
+        self.delete_text(start, end);
+    }
+}
@@ -0,0 +1,693 @@
+use std::fs;
+use std::path::Path;
+use termion::event::Key;
+use prompt::Prompt;
+use encoding::Encoding;
+use gutter::LineNumbers;
+use replace::Substitute;
+use search::SearchCase;
+use text::LineEnding;
+use theme::Theme;
+use Editor;
+
+// What Tab completes in the argument of a command.
+#[derive(Copy, Clone, PartialEq)]
+enum Complete {
+    Nothing,
+    Files,
+    Options,
+}
+
+struct Command {
+    name: &'static str,
+    // Shortest accepted abbreviation of `name`.
+    short: &'static str,
+    range: bool,
+    complete: Complete,
+    run: fn(&mut Editor, &Invocation) -> Result<(), String>,
+}
+
+struct Invocation<'a> {
+    // Rows `first..=last`, the current row when no range was given.
+    first: usize,
+    last: usize,
+    bang: bool,
+    args: &'a str,
+}
+
+static COMMANDS: &[Command] = &[
+    Command { name: "write", short: "w", range: false, complete: Complete::Files, run: Editor::write_command },
+    Command { name: "wq", short: "wq", range: false, complete: Complete::Files, run: Editor::write_quit_command },
+    Command { name: "quit", short: "q", range: false, complete: Complete::Nothing, run: Editor::quit_command },
+    Command { name: "edit", short: "e", range: false, complete: Complete::Files, run: Editor::edit_command },
+    Command { name: "set", short: "se", range: false, complete: Complete::Options, run: Editor::set_command },
+    Command { name: "delete", short: "d", range: true, complete: Complete::Nothing, run: Editor::delete_command },
+    Command { name: "substitute", short: "s", range: true, complete: Complete::Nothing, run: Editor::substitute_command },
+    Command { name: "bnext", short: "bn", range: false, complete: Complete::Nothing, run: Editor::next_buffer_command },
+    Command { name: "bprevious", short: "bp", range: false, complete: Complete::Nothing, run: Editor::previous_buffer_command },
+    Command { name: "buffer", short: "b", range: false, complete: Complete::Nothing, run: Editor::buffer_command },
+    Command { name: "buffers", short: "buffers", range: false, complete: Complete::Nothing, run: Editor::list_buffers_command },
+    Command { name: "ls", short: "ls", range: false, complete: Complete::Nothing, run: Editor::list_buffers_command },
+    Command { name: "split", short: "sp", range: false, complete: Complete::Files, run: Editor::split_command },
+    Command { name: "vsplit", short: "vs", range: false, complete: Complete::Files, run: Editor::vertical_split_command },
+    Command { name: "close", short: "clo", range: false, complete: Complete::Nothing, run: Editor::close_command },
+    Command { name: "only", short: "on", range: false, complete: Complete::Nothing, run: Editor::only_command },
+    Command { name: "config", short: "conf", range: false, complete: Complete::Nothing, run: Editor::config_command },
+    Command { name: "syntax", short: "sy", range: false, complete: Complete::Nothing, run: Editor::syntax_command },
+];
+
+// Option names with their abbreviations.
+static OPTIONS: &[(&str, &str)] = &[
+    ("tabstop", "ts"),
+    ("softtabstop", "sts"),
+    ("searchcase", "sc"),
+    ("quittimes", "qt"),
+    ("messagetimeout", "mto"),
+    ("theme", "theme"),
+    ("filetype", "ft"),
+    ("number", "nu"),
+    ("wrap", "wrap"),
+    ("linebreak", "lbr"),
+    ("showbreak", "sbr"),
+    ("fileformat", "ff"),
+    ("endofline", "eol"),
+    ("bomb", "bomb"),
+    ("fileencoding", "fenc"),
+    ("readonly", "ro"),
+];
+
+// Options of the whole editor rather than of each buffer.
+static GLOBAL_OPTIONS: &[&str] = &["searchcase", "quittimes", "messagetimeout", "theme"];
+
+// Options describing the file itself, which are detected when it is opened
+// and can only be changed with `:set`.
+static FILE_OPTIONS: &[&str] = &["fileformat", "fileencoding", "endofline", "bomb", "readonly", "filetype"];
+
+fn find_command(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name.starts_with(name) && name.starts_with(command.short))
+}
+
+// Length of the leading range (`%`, `10,20`, `.,$-1`, ...) in `input`.
+fn range_len(input: &str) -> usize {
+    input.find(|c: char| !(c.is_ascii_digit() || ".,$%+-".contains(c))).unwrap_or(input.len())
+}
+
+// Parses `%`, `a` or `a,b` into zero based rows of a buffer with `rows`
+// rows and the cursor on row `cy`, swapping a backwards range.
+fn parse_range(input: &str, cy: usize, rows: usize) -> Result<Option<(usize, usize)>, String> {
+    if input.is_empty() {
+        return Ok(None)
+    }
+    if input == "%" {
+        return Ok(Some((0, rows.saturating_sub(1))))
+    }
+    let mut addresses = input.splitn(2, ',');
+    let first = parse_address(addresses.next().unwrap_or(""), cy, rows)?;
+    let last = match addresses.next() {
+        Some(address) => parse_address(address, cy, rows)?,
+        None => first,
+    };
+    if first > rows.max(1) || last > rows.max(1) {
+        return Err("Invalid range".to_string())
+    }
+    let (first, last) = (first.saturating_sub(1), last.saturating_sub(1));
+    Ok(Some((first.min(last), first.max(last))))
+}
+
+// A one based line number: `N`, `.` or `$`, followed by any `+N`/`-N`.
+fn parse_address(input: &str, cy: usize, rows: usize) -> Result<usize, String> {
+    let invalid = || format!("Invalid address: {}", input);
+    let digits = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    let (mut line, mut rest) = if digits > 0 {
+        (input[..digits].parse::<isize>().map_err(|_| invalid())?, &input[digits..])
+    } else if let Some(rest) = input.strip_prefix('.') {
+        (cy as isize + 1, rest)
+    } else if let Some(rest) = input.strip_prefix('$') {
+        (rows as isize, rest)
+    } else {
+        (cy as isize + 1, input)
+    };
+    while !rest.is_empty() {
+        let sign = match rest.chars().next() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => return Err(invalid()),
+        };
+        rest = &rest[1..];
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let offset = if digits == 0 {1} else {rest[..digits].parse::<isize>().map_err(|_| invalid())?};
+        line += sign * offset;
+        rest = &rest[digits..];
+    }
+    if line < 0 {
+        return Err(invalid())
+    }
+    Ok(line as usize)
+}
+
+// Values of on/off options.
+pub fn parse_flag(value: &str) -> Option<bool> {
+    match value {
+        "on" | "yes" | "1" | "true" => Some(true),
+        "off" | "no" | "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn flag_name(flag: bool) -> &'static str {
+    if flag {"on"} else {"off"}
+}
+
+fn option_name(name: &str) -> Result<&'static str, String> {
+    match OPTIONS.iter().find(|&&(long, short)| name == long || name == short) {
+        Some(&(long, _)) => Ok(long),
+        None => Err(format!("Unknown option: {}", name)),
+    }
+}
+
+pub fn is_global_option(name: &str) -> bool {
+    option_name(name).is_ok_and(|name| GLOBAL_OPTIONS.contains(&name))
+}
+
+pub fn is_file_option(name: &str) -> bool {
+    option_name(name).is_ok_and(|name| FILE_OPTIONS.contains(&name))
+}
+
+fn name_len(input: &str) -> usize {
+    input.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(input.len())
+}
+
+pub struct Completion {
+    base: String,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+impl Editor {
+    pub fn command_line(&mut self) {
+        self.completion = None;
+        let command = self.prompt(":".to_string(), Some("command"), Some(Editor::command_callback));
+        self.completion = None;
+        if let Some(command) = command {
+            if let Err(message) = self.run_command(command.trim_start()) {
+                self.set_status_message(message);
+            }
+        }
+    }
+
+    pub fn run_command(&mut self, command: &str) -> Result<(), String> {
+        let split = range_len(command);
+        let range = parse_range(&command[..split], self.buffer().cy, self.buffer().text.len())?;
+        let rest = command[split..].trim_start();
+        let (name, rest) = rest.split_at(name_len(rest));
+        let (bang, args) = match rest.strip_prefix('!') {
+            Some(args) => (true, args),
+            None => (false, rest),
+        };
+        if name.is_empty() {
+            // `:<line>` jumps there.
+            if let Some((_, last)) = range {
+                let buffer = self.buffer_mut();
+                let last = last.min(buffer.text.len().saturating_sub(1));
+                buffer.move_to_row(last);
+            }
+            return Ok(())
+        }
+        let command = match find_command(name) {
+            Some(command) => command,
+            None => return Err(format!("Not an editor command: {}", name)),
+        };
+        if range.is_some() && !command.range {
+            return Err(format!("No range allowed: {}", command.name))
+        }
+        let cy = self.buffer().cy;
+        let (first, last) = range.unwrap_or((cy, cy));
+        let invocation = Invocation { first, last, bang, args: args.trim_start() };
+        (command.run)(self, &invocation)
+    }
+
+    fn write_command(&mut self, invocation: &Invocation) -> Result<(), String> {
+        self.write_args(invocation).map(|_| ())
+    }
+
+    // Saves the buffer, or writes a copy when given another file name.
+    // Returns whether the buffer is now saved.
+    fn write_args(&mut self, invocation: &Invocation) -> Result<bool, String> {
+        let filename = invocation.args.trim();
+        let current = self.buffer().filename.clone();
+        if filename.is_empty() || current.as_ref().is_none_or(|f| f == filename) {
+            if !filename.is_empty() {
+                self.buffer_mut().filename = Some(filename.to_string());
+            }
+            if invocation.bang {
+                self.buffer_mut().read_only = false;
+            }
+            return Ok(self.save(false))
+        }
+        if Path::new(filename).exists() && !invocation.bang {
+            return Err(format!("\"{}\" exists (add ! to override)", filename))
+        }
+        self.write_file(filename);
+        Ok(false)
+    }
+
+    fn write_quit_command(&mut self, invocation: &Invocation) -> Result<(), String> {
+        if self.write_args(invocation)? {
+            if self.windows.len() > 1 {
+                return self.close_window()
+            }
+            self.check_unsaved()?;
+            self.quit = true;
+        }
+        Ok(())
+    }
+
+    // Closes the active window, or leaves the editor from the last one.
+    fn quit_command(&mut self, invocation: &Invocation) -> Result<(), String> {
+        if self.windows.len() > 1 {
+            return self.close_window()
+        }
+        if !invocation.bang {
+            self.check_unsaved()?;
+        }
+        self.quit = true;
+        Ok(())
+    }
+
+    // `:sp [file]` and `:vs [file]` split the active window.
+    fn split_command(&mut self, invocation: &Invocation) -> Result<(), String> {
+        self.split_with(invocation, false)
+    }
+
+    fn vertical_split_command(&mut self, invocation: &Invocation) -> Result<(), String> {
+        self.split_with(invocation, true)
+    }
+
+    fn split_with(&mut self, invocation: &Invocation, vertical: bool) -> Result<(), String> {
+        self.split_window(vertical)?;
+        match invocation.args.trim() {
+            "" => {},
+            filename => self.open_buffer(filename.to_string()),
+        }
+        Ok(())
+    }
+
+    fn close_command(&mut self, _: &Invocation) -> Result<(), String> {
+        self.close_window()
+    }
+
+    fn only_command(&mut self, _: &Invocation) -> Result<(), String> {
+        self.only_window();
+        Ok(())
+    }
+
+    fn check_unsaved(&self) -> Result<(), String> {
+        match self.buffers.iter().position(|buffer| buffer.dirty) {
+            Some(index) => Err(format!("No write since last change for buffer {} \"{}\" (add ! to override)",
+                                       index + 1, self.buffers[index].name())),
+            None => Ok(()),
+        }
+    }
+
+    // `:e file` opens another file in a new buffer, `:e` alone reloads the current one.
+    fn edit_command(&mut self, invocation: &Invocation) -> Result<(), String> {
+        match invocation.args.trim() {
+            "" => {
+                if self.buffer().filename.is_none() {
+                    return Err("No file name".to_string())
+                }
+                if self.buffer().dirty && !invocation.bang {
+                    return Err("No write since last change (add ! to override)".to_string())
+                }
+                self.reload_buffer();
+            },
+            filename => self.open_buffer(filename.to_string()),
+        }
+        Ok(())
+    }
+
+    fn next_buffer_command(&mut self, _: &Invocation) -> Result<(), String> {
+        let index = (self.current() + 1) % self.buffers.len();
+        self.switch_buffer(index);
+        Ok(())
+    }
+
+    fn previous_buffer_command(&mut self, _: &Invocation) -> Result<(), String> {
+        let index = (self.current() + self.buffers.len() - 1) % self.buffers.len();
+        self.switch_buffer(index);
+        Ok(())
+    }
+
+    // `:b N` switches by number, `:b name` to the only buffer whose name contains it.
+    fn buffer_command(&mut self, invocation: &Invocation) -> Result<(), String> {
+        let args = invocation.args.trim();
+        let index = if let Ok(number) = args.parse::<usize>() {
+            if number == 0 || number > self.buffers.len() {
+                return Err(format!("Buffer {} does not exist", number))
+            }
+            number - 1
+        } else {
+            let matches: Vec<usize> = (0..self.buffers.len())
+                .filter(|&i| self.buffers[i].name().contains(args))
+                .collect();
+            match matches.len() {
+                1 => matches[0],
+                0 => return Err(format!("No matching buffer for {}", args)),
+                _ => return Err(format!("More than one match for {}", args)),
+            }
+        };
+        self.switch_buffer(index);
+        Ok(())
+    }
+
+    // `1 %a "main.rs" line 3` for each buffer, `%` marking the current one
+    // and `+` the modified ones.
+    fn list_buffers_command(&mut self, _: &Invocation) -> Result<(), String> {
+        let entries: Vec<String> = self.buffers.iter().enumerate().map(|(i, buffer)| {
+            let current = if i == self.current() {"%a"} else {"  "};
+            let modified = if buffer.dirty {"+"} else {" "};
+            format!("{} {}{} \"{}\" line {}", i + 1, current, modified, buffer.name(), buffer.cy + 1)
+        }).collect();
+        self.set_status_message(entries.join(" | "));
+        Ok(())
+    }
+
+    fn set_command(&mut self, invocation: &Invocation) -> Result<(), String> {
+        if invocation.args.trim().is_empty() {
+            let values: Vec<String> = OPTIONS.iter()
+                .map(|&(name, _)| format!("{}={}", name, self.option_value(name)))
+                .collect();
+            self.set_status_message(values.join(" "));
+            return Ok(())
+        }
+        for assignment in invocation.args.split_whitespace() {
+            self.set_option(assignment)?;
+        }
+        Ok(())
+    }
+
+    // `name=value` sets an option, `name` or `name?` shows its value.
+    fn set_option(&mut self, assignment: &str) -> Result<(), String> {
+        let (name, value) = match assignment.find('=') {
+            Some(i) => (&assignment[..i], Some(&assignment[i + 1..])),
+            None => (assignment.trim_end_matches('?'), None),
+        };
+        match value {
+            Some(value) => {
+                let current = self.current();
+                self.assign_option(current, name, value)
+            },
+            None => {
+                let name = option_name(name)?;
+                let message = format!("{}={}", name, self.option_value(name));
+                self.set_status_message(message);
+                Ok(())
+            },
+        }
+    }
+
+    // Sets option `name` to `value`, for buffer `index` if it is one that
+    // buffers have their own of.
+    pub fn assign_option(&mut self, index: usize, name: &str, value: &str) -> Result<(), String> {
+        let name = option_name(name)?;
+        let invalid = || format!("Invalid value for {}: {}", name, value);
+        let buffer = &mut self.buffers[index];
+        match name {
+            "tabstop" => match value.parse::<usize>() {
+                Ok(width) if width > 0 => buffer.tab_stop = width,
+                _ => return Err(invalid()),
+            },
+            "softtabstop" => buffer.soft_tab = value.parse().map_err(|_| invalid())?,
+            "searchcase" => self.search_case = SearchCase::from_name(value).ok_or_else(invalid)?,
+            "quittimes" => self.quit_confirmations = value.parse().map_err(|_| invalid())?,
+            "messagetimeout" => self.message_timeout = value.parse().map_err(|_| invalid())?,
+            "theme" => {
+                self.colors = Theme::load(value).map_err(|err| format!("Invalid theme {}: {}", value, err))?;
+                self.theme = value.to_string();
+                self.redraw();
+            },
+            "filetype" => {
+                let (syntax, syntax_re) = self.syntaxes.filetype(value).ok_or_else(|| format!("Unknown filetype: {}", value))?;
+                buffer.set_syntax(syntax, syntax_re);
+            },
+            "number" => buffer.line_numbers = LineNumbers::from_name(value).ok_or_else(invalid)?,
+            "wrap" => buffer.wrap = parse_flag(value).ok_or_else(invalid)?,
+            "linebreak" => buffer.linebreak = parse_flag(value).ok_or_else(invalid)?,
+            "showbreak" => buffer.showbreak = value.to_string(),
+            "readonly" => buffer.read_only = parse_flag(value).ok_or_else(invalid)?,
+            "fileformat" | "endofline" | "bomb" | "fileencoding" => {
+                let mut format = buffer.format;
+                match name {
+                    "fileformat" => {
+                        format.line_ending = LineEnding::from_name(value).ok_or_else(invalid)?;
+                        format.keep_cr = false;
+                    },
+                    "endofline" => format.final_newline = parse_flag(value).ok_or_else(invalid)?,
+                    "fileencoding" => {
+                        format.encoding = Encoding::from_name(value).ok_or_else(invalid)?;
+                        format.bom = format.encoding == Encoding::Utf16Le || format.encoding == Encoding::Utf16Be;
+                    },
+                    _ => format.bom = parse_flag(value).ok_or_else(invalid)?,
+                }
+                buffer.set_format(format);
+            },
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn option_value(&self, name: &str) -> String {
+        match name {
+            "tabstop" => self.buffer().tab_stop.to_string(),
+            "softtabstop" => self.buffer().soft_tab.to_string(),
+            "searchcase" => self.search_case.name().to_string(),
+            "quittimes" => self.quit_confirmations.to_string(),
+            "messagetimeout" => self.message_timeout.to_string(),
+            "theme" => self.theme.clone(),
+            "filetype" => self.buffer().syntax.filetype.clone(),
+            "number" => self.buffer().line_numbers.name().to_string(),
+            "wrap" => flag_name(self.buffer().wrap).to_string(),
+            "linebreak" => flag_name(self.buffer().linebreak).to_string(),
+            "showbreak" => self.buffer().showbreak.clone(),
+            "fileformat" => self.buffer().format.line_ending.name().to_string(),
+            "endofline" => flag_name(self.buffer().format.final_newline).to_string(),
+            "bomb" => flag_name(self.buffer().format.bom).to_string(),
+            "fileencoding" => self.buffer().format.encoding.name().to_string(),
+            "readonly" => flag_name(self.buffer().read_only).to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn config_command(&mut self, _: &Invocation) -> Result<(), String> {
+        self.load_config()?;
+        self.set_status_message("Config reloaded".to_string());
+        Ok(())
+    }
+
+    // `:syntax list` shows the known filetypes and any definitions that were
+    // left out, `:syntax reload` reads them all again.
+    fn syntax_command(&mut self, invocation: &Invocation) -> Result<(), String> {
+        match invocation.args.trim() {
+            "" | "list" => {
+                let mut message = format!("Syntaxes: {}", self.syntaxes.filetypes().join(", "));
+                for warning in &self.syntaxes.warnings {
+                    message += &format!(" | {}", warning);
+                }
+                self.set_status_message(message);
+            },
+            "reload" => {
+                self.load_syntaxes()?;
+                let message = format!("Reloaded {} syntaxes", self.syntaxes.filetypes().len());
+                self.set_status_message(message);
+            },
+            args => return Err(format!("Invalid argument: {}", args)),
+        }
+        Ok(())
+    }
+
+    fn delete_command(&mut self, invocation: &Invocation) -> Result<(), String> {
+        let buffer = self.buffer_mut();
+        if buffer.text.is_empty() {return Ok(())}
+        let count = invocation.last.min(buffer.text.len() - 1) + 1 - invocation.first;
+        buffer.delete_rows(invocation.first, invocation.last);
+        if count > 1 {
+            self.set_status_message(format!("{} fewer lines", count));
+        }
+        Ok(())
+    }
+
+    fn substitute_command(&mut self, invocation: &Invocation) -> Result<(), String> {
+        let substitute = Substitute::parse(invocation.args)
+            .ok_or_else(|| format!("Invalid substitute command: s{}", invocation.args))?;
+        self.substitute(invocation.first, invocation.last, &substitute);
+        Ok(())
+    }
+
+    fn command_callback(editor: &mut Editor, prompt: &mut Prompt, key: Key) {
+        if key != Key::Char('\t') {
+            editor.completion = None;
+            return
+        }
+        prompt.buffer.pop();
+        editor.complete(&mut prompt.buffer);
+    }
+
+    // Tab extends the word under completion to the longest common prefix
+    // of its candidates, further presses cycle through them.
+    fn complete(&mut self, buffer: &mut String) {
+        if let Some(ref mut completion) = self.completion {
+            let current = format!("{}{}", completion.base, completion.candidates[completion.index]);
+            if *buffer == current {
+                completion.index = (completion.index + 1) % completion.candidates.len();
+                *buffer = format!("{}{}", completion.base, completion.candidates[completion.index]);
+                return
+            }
+        }
+        self.completion = None;
+        let (base, word, candidates) = completions(buffer);
+        if candidates.is_empty() {
+            return
+        }
+        let prefix = common_prefix(&candidates);
+        if candidates.len() == 1 || prefix.len() > word.len() {
+            *buffer = format!("{}{}", base, prefix);
+        } else {
+            *buffer = format!("{}{}", base, candidates[0]);
+            self.completion = Some(Completion { base, candidates, index: 0 });
+        }
+    }
+}
+
+// Splits `buffer` into the part kept as is, the word being completed and
+// the candidates for that word.
+fn completions(buffer: &str) -> (String, String, Vec<String>) {
+    let start = range_len(buffer);
+    let rest = &buffer[start..];
+    let name = &rest[..name_len(rest)];
+    if name.len() == rest.len() {
+        let candidates = COMMANDS.iter()
+            .map(|command| command.name.to_string())
+            .filter(|candidate| candidate.starts_with(name))
+            .collect();
+        return (buffer[..start].to_string(), name.to_string(), candidates)
+    }
+    let word_start = buffer.trim_end_matches(|c: char| !c.is_whitespace()).len();
+    let word = &buffer[word_start..];
+    let complete = match find_command(name) {
+        Some(command) if word_start > 0 => command.complete,
+        _ => Complete::Nothing,
+    };
+    let candidates = match complete {
+        Complete::Files => file_candidates(word),
+        Complete::Options if !word.contains('=') => OPTIONS.iter()
+            .map(|&(option, _)| option.to_string())
+            .filter(|option| option.starts_with(word))
+            .collect(),
+        _ => Vec::new(),
+    };
+    (buffer[..word_start].to_string(), word.to_string(), candidates)
+}
+
+// Entries of the directory part of `word` starting with its last component,
+// directories with a trailing `/`. Hidden files are only offered for a `.` prefix.
+fn file_candidates(word: &str) -> Vec<String> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word),
+    };
+    let entries = match fs::read_dir(if dir.is_empty() {"."} else {dir}) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut candidates: Vec<String> = entries.filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None
+            }
+            let slash = if entry.path().is_dir() {"/"} else {""};
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+fn common_prefix(words: &[String]) -> String {
+    let first = &words[0];
+    let mut len = first.len();
+    for word in &words[1..] {
+        let common = first.char_indices().zip(word.chars())
+            .take_while(|&((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, a), _)| i + a.len_utf8());
+        len = len.min(common);
+    }
+    first[..len].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_command, parse_range, range_len};
+
+    // A ten row buffer with the cursor on the fifth row.
+    fn range(input: &str) -> Result<Option<(usize, usize)>, String> {
+        parse_range(input, 4, 10)
+    }
+
+    #[test]
+    fn parses_line_numbers_and_symbols() {
+        assert_eq!(range(""), Ok(None));
+        assert_eq!(range("%"), Ok(Some((0, 9))));
+        assert_eq!(range("3"), Ok(Some((2, 2))));
+        assert_eq!(range("3,5"), Ok(Some((2, 4))));
+        assert_eq!(range("."), Ok(Some((4, 4))));
+        assert_eq!(range(".,$"), Ok(Some((4, 9))));
+        assert_eq!(parse_range("%", 0, 0), Ok(Some((0, 0))));
+        assert_eq!(parse_range("1", 0, 0), Ok(Some((0, 0))));
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(range("$-1"), Ok(Some((8, 8))));
+        assert_eq!(range(".+2"), Ok(Some((6, 6))));
+        assert_eq!(range("+3"), Ok(Some((7, 7))));
+        assert_eq!(range("+"), Ok(Some((5, 5))));
+        assert_eq!(range("-"), Ok(Some((3, 3))));
+        assert_eq!(range(".,+2"), Ok(Some((4, 6))));
+        assert_eq!(range("2+1-1"), Ok(Some((1, 1))));
+    }
+
+    #[test]
+    fn swaps_backwards_ranges() {
+        assert_eq!(range("5,3"), Ok(Some((2, 4))));
+        assert_eq!(range("$,."), Ok(Some((4, 9))));
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        for input in &["11", "$+1", "1-5", "1,2,3", ".$", "%,1", "1,11"] {
+            assert!(range(input).is_err(), "{:?} should not parse", input);
+        }
+    }
+
+    #[test]
+    fn splits_range_from_command() {
+        assert_eq!(range_len("10,20d"), 5);
+        assert_eq!(range_len("%s/a/b/"), 1);
+        assert_eq!(range_len(".,$-1d"), 5);
+        assert_eq!(range_len("set"), 0);
+    }
+
+    #[test]
+    fn finds_commands_by_abbreviation() {
+        let name = |input| find_command(input).map(|command| command.name);
+        assert_eq!(name("s"), Some("substitute"));
+        assert_eq!(name("se"), Some("set"));
+        assert_eq!(name("wq"), Some("wq"));
+        assert_eq!(name("buffers"), Some("buffers"));
+        assert_eq!(name("bn"), Some("bnext"));
+        assert_eq!(name("cl"), None);
+        assert_eq!(name("writes"), None);
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use termion::event::Key;
+use toml::value::Value;
+use buffer::Buffer;
+use command::{flag_name, is_file_option, is_global_option};
+use error::{self, Error};
+use gutter::LineNumbers;
+use search::SearchCase;
+use theme::Theme;
+use wrap::KILO_SHOWBREAK;
+use {Editor, KILO_MESSAGE_TIMEOUT, KILO_QUIT_TIMES, KILO_SOFT_TAB, KILO_TAB_STOP, KILO_THEME};
+
+// Looked for in the directory of a file and every directory above it.
+const PROJECT_CONFIG: &str = ".kilo-rs.toml";
+
+// Settings from a config file. Top level keys are `:set` options, and the
+// `[keys]` table binds normal mode keys to commands:
+//
+//     tabstop = 4
+//     number = "relative"
+//     [keys]
+//     ctrl-t = "set wrap=on"
+pub struct Config {
+    options: Vec<(String, String)>,
+    keys: HashMap<Key, String>,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            options: Vec::new(),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+// `$XDG_CONFIG_HOME/kilo-rs`, or under `~/.config` without it.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("kilo-rs"))
+}
+
+// The `kilo-rs` directories under `$XDG_DATA_DIRS`, the most important
+// first.
+pub fn data_dirs() -> Vec<PathBuf> {
+    let dirs = env::var("XDG_DATA_DIRS").ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    dirs.split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(dir).join("kilo-rs"))
+        .collect()
+}
+
+fn config_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+// Project config files that apply to `filename`, the nearest last.
+fn project_paths(filename: &str) -> Vec<PathBuf> {
+    let path = Path::new(filename);
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.to_path_buf())
+    };
+    let mut paths: Vec<PathBuf> = path.ancestors().skip(1)
+        .map(|dir| dir.join(PROJECT_CONFIG))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.reverse();
+    paths
+}
+
+// The config in `path`, none when there is no such file.
+fn read_config(path: &Path) -> error::Result<Option<Config>> {
+    let mut contents = String::new();
+    match File::open(path) {
+        Ok(mut file) => file.read_to_string(&mut contents)?,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(Error::Io(err)),
+    };
+    let table = match contents.parse::<Value>()? {
+        Value::Table(table) => table,
+        _ => return Err(Error::Config("expected a table".to_string())),
+    };
+    let mut config = Config::new();
+    for (name, value) in table {
+        if name == "keys" {
+            let keys = value.as_table().ok_or_else(|| Error::Config("`keys` must be a table".to_string()))?;
+            for (key, command) in keys {
+                let key = parse_key(key).ok_or_else(|| Error::Config(format!("unknown key `{}`", key)))?;
+                let command = command.as_str().ok_or_else(|| Error::Config(format!("the command for `{}` must be a string", key_name(key))))?;
+                config.keys.insert(key, command.to_string());
+            }
+            continue
+        }
+        let value = match value {
+            Value::String(value) => value,
+            Value::Integer(value) => value.to_string(),
+            Value::Boolean(value) => flag_name(value).to_string(),
+            _ => return Err(Error::Config(format!("`{}` must be a string, number or boolean", name))),
+        };
+        config.options.push((name, value));
+    }
+    Ok(Some(config))
+}
+
+// Key names as written in `[keys]`: `x`, `ctrl-x`, `alt-x`, `f5`, `space`,
+// `tab`, `enter` and the names of the cursor keys. Case only matters for
+// plain characters.
+fn parse_key(name: &str) -> Option<Key> {
+    let single = |name: &str| {
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+    if let Some(c) = single(name) {
+        return Some(Key::Char(c))
+    }
+    if let Some(rest) = strip_prefix_ignore_case(name, "ctrl-") {
+        return single(rest).map(|c| Key::Ctrl(c.to_ascii_lowercase()))
+    }
+    if let Some(rest) = strip_prefix_ignore_case(name, "alt-") {
+        return single(rest).map(Key::Alt)
+    }
+    let name = name.to_ascii_lowercase();
+    let key = match name.as_str() {
+        "space" => Key::Char(' '),
+        "tab" => Key::Char('\t'),
+        "enter" => Key::Char('\n'),
+        "backspace" => Key::Backspace,
+        "delete" => Key::Delete,
+        "insert" => Key::Insert,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        _ => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+            Some(n) if (1..=12).contains(&n) => Key::F(n),
+            _ => return None,
+        },
+    };
+    Some(key)
+}
+
+fn strip_prefix_ignore_case<'a>(name: &'a str, prefix: &str) -> Option<&'a str> {
+    match name.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&name[prefix.len()..]),
+        _ => None,
+    }
+}
+
+fn key_name(key: Key) -> String {
+    match key {
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("ctrl-{}", c),
+        Key::Alt(c) => format!("alt-{}", c),
+        Key::F(n) => format!("f{}", n),
+        key => format!("{:?}", key).to_lowercase(),
+    }
+}
+
+impl Buffer {
+    // Back to the built in settings, before a config is applied again.
+    fn reset_options(&mut self) {
+        self.tab_stop = KILO_TAB_STOP;
+        self.soft_tab = KILO_SOFT_TAB;
+        self.line_numbers = LineNumbers::Off;
+        self.wrap = false;
+        self.linebreak = false;
+        self.showbreak = KILO_SHOWBREAK.to_string();
+    }
+}
+
+impl Editor {
+    // Reads the user config and applies it to the editor and every open
+    // buffer. Settings changed with `:set` are reset.
+    pub fn load_config(&mut self) -> Result<(), String> {
+        self.quit_confirmations = KILO_QUIT_TIMES;
+        self.message_timeout = KILO_MESSAGE_TIMEOUT;
+        self.search_case = SearchCase::Smart;
+        self.theme = KILO_THEME.to_string();
+        self.colors = Theme::new();
+        let mut errors = Vec::new();
+        let path = config_path();
+        self.config = match path.as_ref().map_or(Ok(None), |path| read_config(path)) {
+            Ok(config) => config.unwrap_or_else(Config::new),
+            Err(err) => {
+                errors.push(format!("Invalid \"{}\": {}", path.unwrap_or_default().display(), err));
+                Config::new()
+            },
+        };
+        let options = self.config.options.clone();
+        for (name, value) in options.iter().filter(|&(name, _)| is_global_option(name)) {
+            let current = self.current();
+            if let Err(err) = self.assign_option(current, name, value) {
+                errors.push(format!("config.toml: {}", err));
+            }
+        }
+        for index in 0..self.buffers.len() {
+            errors.extend(self.configure_buffer(index).err());
+        }
+        if errors.is_empty() {Ok(())} else {Err(errors.join(" | "))}
+    }
+
+    // Applies the buffer options of the user config to buffer `index`, then
+    // the project configs above its file. Only buffer options are taken from
+    // project configs; editor options are set once by `load_config`. Options
+    // of the file itself are never taken from a config.
+    pub fn configure_buffer(&mut self, index: usize) -> Result<(), String> {
+        self.buffers[index].reset_options();
+        let mut errors = Vec::new();
+        let options = self.config.options.clone();
+        for (name, value) in options.into_iter().filter(|(name, _)| !is_global_option(name)) {
+            let result = if is_file_option(&name) {
+                Err(format!("{} can only be set with :set", name))
+            } else {
+                self.assign_option(index, &name, &value)
+            };
+            if let Err(err) = result {
+                errors.push(format!("config.toml: {}", err));
+            }
+        }
+        let filename = self.buffers[index].filename.clone();
+        for path in filename.map_or(Vec::new(), |filename| project_paths(&filename)) {
+            let options = match read_config(&path) {
+                Ok(config) => config.map_or(Vec::new(), |config| config.options),
+                Err(err) => {
+                    errors.push(format!("Invalid \"{}\": {}", path.display(), err));
+                    continue
+                },
+            };
+            for (name, value) in options {
+                let result = if is_global_option(&name) {
+                    Err(format!("{} can only be set in config.toml", name))
+                } else if is_file_option(&name) {
+                    Err(format!("{} can only be set with :set", name))
+                } else {
+                    self.assign_option(index, &name, &value)
+                };
+                if let Err(err) = result {
+                    errors.push(format!("{}: {}", path.display(), err));
+                }
+            }
+        }
+        if errors.is_empty() {Ok(())} else {Err(errors.join(" | "))}
+    }
+
+    // Runs the command bound to `key`, if it is bound.
+    pub fn run_binding(&mut self, key: Key) -> bool {
+        let command = match self.config.keys.get(&key) {
+            Some(command) => command.clone(),
+            None => return false,
+        };
+        if let Err(message) = self.run_command(command.trim_start_matches(':')) {
+            self.set_status_message(message);
+        }
+        true
+    }
+}
@@ -0,0 +1,136 @@
+use std::io;
+use std::io::Write;
+
+// How many leading bytes are looked at to tell text from binary files.
+const BINARY_SNIFF_LEN: usize = 8192;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    // Every byte maps to the char with the same value, so any file can be
+    // read and written back unchanged.
+    Latin1,
+}
+
+impl Encoding {
+    // Name used by `:set fileencoding=`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin1",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        let name = name.to_ascii_lowercase();
+        let name = match name.as_str() {
+            "utf8" => "utf-8",
+            "iso-8859-1" | "latin-1" => "latin1",
+            name => name,
+        };
+        [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be, Encoding::Latin1].iter()
+            .cloned()
+            .find(|encoding| encoding.name() == name)
+    }
+
+    // Byte order mark, empty for Latin-1 which has none.
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => b"\xef\xbb\xbf",
+            Encoding::Utf16Le => b"\xff\xfe",
+            Encoding::Utf16Be => b"\xfe\xff",
+            Encoding::Latin1 => b"",
+        }
+    }
+
+    // Writes `text` encoded, returning the number of bytes written.
+    pub fn write<W: Write>(self, writer: &mut W, text: &str) -> io::Result<usize> {
+        match self {
+            Encoding::Utf8 => {
+                writer.write_all(text.as_bytes())?;
+                Ok(text.len())
+            },
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut bytes = Vec::with_capacity(text.len() * 2);
+                for unit in text.encode_utf16() {
+                    let pair = if self == Encoding::Utf16Le {unit.to_le_bytes()} else {unit.to_be_bytes()};
+                    bytes.extend_from_slice(&pair);
+                }
+                writer.write_all(&bytes)?;
+                Ok(bytes.len())
+            },
+            Encoding::Latin1 => {
+                let mut bytes = Vec::with_capacity(text.len());
+                for c in text.chars() {
+                    if c as u32 > 0xff {
+                        let message = format!("{:?} can't be encoded as latin1", c);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, message))
+                    }
+                    bytes.push(c as u8);
+                }
+                writer.write_all(&bytes)?;
+                Ok(bytes.len())
+            },
+        }
+    }
+}
+
+pub struct Decoded {
+    pub text: String,
+    pub encoding: Encoding,
+    pub bom: bool,
+    pub binary: bool,
+}
+
+// UTF-16 is only recognised by its BOM. Anything that is not valid in the
+// detected encoding is read as Latin-1 rather than being replaced, so that
+// saving gives back the same bytes.
+pub fn decode(mut bytes: Vec<u8>) -> Decoded {
+    let bom = Encoding::Utf8.bom();
+    if bytes.starts_with(bom) {
+        // Valid text is taken over in place; files can be large.
+        bytes.drain(..bom.len());
+        match String::from_utf8(bytes) {
+            Ok(text) => return Decoded { text, encoding: Encoding::Utf8, bom: true, binary: false },
+            Err(err) => {
+                bytes = err.into_bytes();
+                bytes.splice(0..0, bom.iter().cloned());
+            },
+        }
+    }
+    for &encoding in &[Encoding::Utf16Le, Encoding::Utf16Be] {
+        if let Some(rest) = bytes.strip_prefix(encoding.bom()) {
+            if let Some(text) = decode_utf16(rest, encoding) {
+                return Decoded { text, encoding, bom: true, binary: false }
+            }
+        }
+    }
+    let binary = bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0);
+    let bytes = if binary {
+        bytes
+    } else {
+        match String::from_utf8(bytes) {
+            Ok(text) => return Decoded { text, encoding: Encoding::Utf8, bom: false, binary },
+            Err(err) => err.into_bytes(),
+        }
+    };
+    Decoded {
+        text: bytes.iter().map(|&byte| byte as char).collect(),
+        encoding: Encoding::Latin1,
+        bom: false,
+        binary,
+    }
+}
+
+fn decode_utf16(bytes: &[u8], encoding: Encoding) -> Option<String> {
+    if bytes.len() % 2 != 0 {return None}
+    let units = bytes.chunks(2).map(|pair| {
+        let pair = [pair[0], pair[1]];
+        if encoding == Encoding::Utf16Le {u16::from_le_bytes(pair)} else {u16::from_be_bytes(pair)}
+    });
+    char::decode_utf16(units).collect::<Result<String, _>>().ok()
+}
@@ -0,0 +1,47 @@
+use regex;
+use std::fmt;
+use std::io;
+use std::result;
+use toml;
+
+// Failures that are reported in the message bar instead of ending the
+// editor, or printed when the terminal itself is unusable.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    Regex(regex::Error),
+    Syntax(String),
+    Config(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "{}", err),
+            Error::Toml(ref err) => write!(f, "{}", err),
+            Error::Regex(ref err) => write!(f, "{}", err),
+            Error::Syntax(ref message) | Error::Config(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Error {
+        Error::Toml(err)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(err: regex::Error) -> Error {
+        Error::Regex(err)
+    }
+}
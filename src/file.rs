@@ -0,0 +1,165 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::{Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use libc;
+
+// How `save` wrote a file.
+#[derive(Copy, Clone, PartialEq)]
+pub enum SaveMethod {
+    // Through a temporary file renamed over the original.
+    Atomic,
+    // By copying a temporary file over the original's contents.
+    InPlace,
+}
+
+// Saves `path` like `write_atomic` where it can. A file with other hard
+// links or another owner, or whose directory won't take a temporary file,
+// is written in place instead, which keeps the links and the owner but is
+// not atomic.
+pub fn save<F>(path: &str, write: F) -> io::Result<(usize, SaveMethod)>
+    where F: FnOnce(&mut File) -> io::Result<usize>
+{
+    let target = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let metadata = fs::metadata(&target).ok();
+    let shared = metadata.as_ref()
+        .is_some_and(|metadata| metadata.nlink() > 1 || metadata.uid() != unsafe { libc::geteuid() });
+    if !shared {
+        match create_temp(&target) {
+            Ok(temp) => return replace(&target, temp, true, write).map(|written| (written, SaveMethod::Atomic)),
+            Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied && metadata.is_some() => {},
+            Err(err) => return Err(err),
+        }
+    }
+    save_in_place(&target, write).map(|written| (written, SaveMethod::InPlace))
+}
+
+// The new contents are written to a temporary file first, next to the file
+// or else in the system's temporary directory, and copied over the original
+// from there. Should the copy fail, the temporary file is kept and named in
+// the error.
+fn save_in_place<F>(path: &Path, write: F) -> io::Result<usize>
+    where F: FnOnce(&mut File) -> io::Result<usize>
+{
+    let (temp, mut copy) = match create_temp(path) {
+        Ok(temp) => temp,
+        Err(_) => create_temp(&env::temp_dir().join(path.file_name().unwrap_or_default()))?,
+    };
+    let written = write(&mut copy)
+        .and_then(|written| copy.sync_all().map(|_| written))
+        .and_then(|written| copy.seek(SeekFrom::Start(0)).map(|_| written));
+    let written = match written {
+        Ok(written) => written,
+        Err(err) => {
+            let _ = fs::remove_file(&temp);
+            return Err(err)
+        },
+    };
+    let mut file = match OpenOptions::new().write(true).open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            let _ = fs::remove_file(&temp);
+            return Err(err)
+        },
+    };
+    let copied = file.set_len(0)
+        .and_then(|_| io::copy(&mut copy, &mut file))
+        .and_then(|_| file.sync_all());
+    if let Err(err) = copied {
+        return Err(io::Error::new(err.kind(), format!("{}, the new text is in \"{}\"", err, temp.display())))
+    }
+    let _ = fs::remove_file(&temp);
+    Ok(written)
+}
+
+// Writes `path` through a temporary file in the same directory that is
+// synced and then renamed over the original, so a failed save never leaves
+// a truncated file behind. The original's permissions are kept and
+// symlinks are written through to their target. Without `durable` neither
+// the file nor its directory is synced, which only a crash of the whole
+// system can tell.
+pub fn write_atomic<F>(path: &str, durable: bool, write: F) -> io::Result<usize>
+    where F: FnOnce(&mut File) -> io::Result<usize>
+{
+    let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let temp = create_temp(&path)?;
+    replace(&path, temp, durable, write)
+}
+
+// Writes the temporary file `temp` and renames it over `path`.
+fn replace<F>(path: &Path, (temp, mut file): (PathBuf, File), durable: bool, write: F) -> io::Result<usize>
+    where F: FnOnce(&mut File) -> io::Result<usize>
+{
+    let metadata = fs::metadata(path).ok();
+    let permissions = metadata.as_ref().map(|metadata| metadata.permissions());
+    // Taking over the group may not be allowed, the file is saved anyway.
+    if let Some(ref metadata) = metadata {
+        unsafe { libc::fchown(file.as_raw_fd(), !0, metadata.gid()) };
+    }
+    let result = write(&mut file)
+        .and_then(|written| if durable {file.sync_all().map(|_| written)} else {Ok(written)})
+        .and_then(|written| match permissions {
+            Some(ref permissions) => fs::set_permissions(&temp, permissions.clone()).map(|_| written),
+            None => Ok(written),
+        })
+        .and_then(|written| fs::rename(&temp, path).map(|_| written));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+        return result
+    }
+    // Make the rename itself durable; not every platform can sync a directory.
+    if let Some(dir) = path.parent().filter(|_| durable) {
+        let dir = if dir.as_os_str().is_empty() {Path::new(".")} else {dir};
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    result
+}
+
+// How many names are tried for the temporary file before giving up.
+const TEMP_ATTEMPTS: usize = 100;
+
+// Creates the temporary file for `path`. A crashed process whose pid was
+// reused may have left one behind under the first name, so others are
+// tried after it.
+fn create_temp(path: &Path) -> io::Result<(PathBuf, File)> {
+    let name = path.file_name().map_or("kilo".into(), |name| name.to_string_lossy());
+    for attempt in 0..TEMP_ATTEMPTS {
+        let suffix = if attempt == 0 {String::new()} else {format!("-{}", attempt)};
+        let temp = path.with_file_name(format!(".{}.{}{}.tmp", name, process::id(), suffix));
+        match OpenOptions::new().read(true).write(true).create_new(true).open(&temp) {
+            Ok(file) => return Ok((temp, file)),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "no free name for a temporary file"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::process;
+    use super::{save, SaveMethod};
+
+    #[test]
+    fn hard_linked_files_are_saved_in_place() {
+        let dir = env::temp_dir().join(format!("kilo-save-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (path, link) = (dir.join("file"), dir.join("link"));
+        fs::write(&path, "old").unwrap();
+        fs::hard_link(&path, &link).unwrap();
+        let result = save(&path.to_string_lossy(), |file| file.write_all(b"new").map(|_| 3));
+        let linked = fs::read_to_string(&link).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.unwrap() == (3, SaveMethod::InPlace));
+        assert_eq!(linked, "new");
+    }
+}
@@ -0,0 +1,200 @@
+use std::path::Path;
+use syntax::Syntax;
+use text::Text;
+
+// How many rows at either end of a file are searched for a modeline.
+const MODELINE_ROWS: usize = 5;
+
+// What a file's name and first and last rows say about its filetype.
+pub struct FileInfo {
+    name: String,
+    path: String,
+    interpreter: Option<String>,
+    pub modeline: Option<String>,
+}
+
+impl FileInfo {
+    pub fn new(filename: &str, text: &Text) -> FileInfo {
+        let name = Path::new(filename).file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+        let first = if text.is_empty() {String::new()} else {text.line(0).to_string()};
+        let rows = (0..text.len().min(MODELINE_ROWS)).chain(text.len().saturating_sub(MODELINE_ROWS).max(MODELINE_ROWS)..text.len());
+        FileInfo {
+            name,
+            path: filename.to_string(),
+            interpreter: interpreter(&first),
+            modeline: rows.filter_map(|y| modeline(&text.line(y).to_string())).next(),
+        }
+    }
+
+    // The full file name is listed, or it ends in a listed extension.
+    // Extensions are compared whole, so `ml` doesn't take `Cargo.toml`.
+    pub fn has_name(&self, syntax: &Syntax) -> bool {
+        syntax.filenames.iter().any(|entry| {
+            let entry = entry.trim_start_matches('.');
+            !entry.is_empty() && (self.name == entry || self.name.ends_with(&format!(".{}", entry)))
+        })
+    }
+
+    // Patterns with a `/` are matched against the path, others against the
+    // file name.
+    pub fn matches_glob(&self, syntax: &Syntax) -> bool {
+        syntax.globs.iter().any(|pattern| {
+            let subject = if pattern.contains('/') {&self.path} else {&self.name};
+            glob_match(pattern, subject)
+        })
+    }
+
+    pub fn runs_with(&self, syntax: &Syntax) -> bool {
+        let interpreter = match self.interpreter {
+            Some(ref interpreter) => interpreter,
+            None => return false,
+        };
+        // `python3.11` runs `python` scripts too.
+        let family = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        syntax.interpreters.iter().any(|name| name == interpreter || name == family)
+    }
+}
+
+// The program a `#!` line runs, looking through `env`.
+fn interpreter(first: &str) -> Option<String> {
+    let mut words = first.strip_prefix("#!")?.split_whitespace();
+    let program = Path::new(words.next()?).file_name()?.to_string_lossy().to_string();
+    if program != "env" {
+        return Some(program)
+    }
+    words.find(|word| !word.starts_with('-') && !word.contains('=')).map(str::to_string)
+}
+
+// The filetype set by a vim modeline such as `// vim: set ft=rust:` or
+// `# vi: filetype=python ts=4`, or one of our own starting with `kilo:`.
+fn modeline(row: &str) -> Option<String> {
+    for marker in &["vim:", "vi:", "ex:", "kilo:"] {
+        let start = match row.find(marker) {
+            Some(start) => start,
+            None => continue,
+        };
+        if start > 0 && !row[..start].ends_with(char::is_whitespace) {
+            continue
+        }
+        let options = &row[start + marker.len()..];
+        for option in options.split(|c: char| c.is_whitespace() || c == ':') {
+            for name in &["ft=", "filetype=", "syntax=", "syn="] {
+                if let Some(filetype) = option.strip_prefix(name).filter(|filetype| !filetype.is_empty()) {
+                    return Some(filetype.to_string())
+                }
+            }
+        }
+    }
+    None
+}
+
+// Shell style wildcards: `*`, `?`, and `[a-z]` or `[!abc]` classes.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_chars(&pattern, &text)
+}
+
+fn match_chars(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => (0..=text.len()).any(|skip| match_chars(&pattern[1..], &text[skip..])),
+        Some('?') => !text.is_empty() && match_chars(&pattern[1..], &text[1..]),
+        Some('[') => {
+            let end = match pattern.iter().skip(2).position(|&c| c == ']') {
+                Some(end) => end + 2,
+                None => return text.first() == Some(&'[') && match_chars(&pattern[1..], &text[1..]),
+            };
+            let c = match text.first() {
+                Some(&c) => c,
+                None => return false,
+            };
+            let (negated, class) = match pattern[1] {
+                '!' | '^' => (true, &pattern[2..end]),
+                _ => (false, &pattern[1..end]),
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    found |= class[i] <= c && c <= class[i + 2];
+                    i += 3;
+                } else {
+                    found |= class[i] == c;
+                    i += 1;
+                }
+            }
+            found != negated && match_chars(&pattern[end + 1..], &text[1..])
+        },
+        Some(&c) => text.first() == Some(&c) && match_chars(&pattern[1..], &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding;
+    use syntax::Syntax;
+    use text::Text;
+    use super::{glob_match, interpreter, modeline, FileInfo};
+
+    fn info(filename: &str, content: &str) -> FileInfo {
+        let (text, _) = Text::from_decoded(encoding::decode(content.as_bytes().to_vec()));
+        FileInfo::new(filename, &text)
+    }
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match("*.[ch]", "main.c"));
+        assert!(glob_match("*.[ch]", "main.h"));
+        assert!(!glob_match("*.[ch]", "main.cc"));
+        assert!(glob_match("*.[!ch]", "main.s"));
+        assert!(!glob_match("*.[^ch]", "main.c"));
+        assert!(glob_match("[a-c]?.txt", "b1.txt"));
+        assert!(!glob_match("[a-c]?.txt", "d1.txt"));
+        assert!(glob_match("*rc", ".bashrc"));
+        assert!(glob_match("*/.git/config", "repo/.git/config"));
+        assert!(glob_match("[x", "[x"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("Makefile", "Makefile.am"));
+    }
+
+    #[test]
+    fn finds_interpreters() {
+        assert_eq!(interpreter("#!/bin/sh"), Some("sh".to_string()));
+        assert_eq!(interpreter("#!/usr/bin/env python3"), Some("python3".to_string()));
+        assert_eq!(interpreter("#! /usr/bin/env -S python3 -u"), Some("python3".to_string()));
+        assert_eq!(interpreter("#!/usr/bin/env LANG=C perl -w"), Some("perl".to_string()));
+        assert_eq!(interpreter("#!/usr/bin/env"), None);
+        assert_eq!(interpreter("#!"), None);
+        assert_eq!(interpreter("# /bin/sh"), None);
+    }
+
+    #[test]
+    fn reads_modelines() {
+        assert_eq!(modeline("// vim: set ft=rust:"), Some("rust".to_string()));
+        assert_eq!(modeline("# vi: filetype=python ts=4"), Some("python".to_string()));
+        assert_eq!(modeline("/* vim:noai:syntax=c */"), Some("c".to_string()));
+        assert_eq!(modeline("# kilo: ft=toml"), Some("toml".to_string()));
+        assert_eq!(modeline("vim: syn=sh"), Some("sh".to_string()));
+        assert_eq!(modeline("index: ft=c"), None);
+        assert_eq!(modeline("# vim: ts=4"), None);
+        assert_eq!(modeline("# vim: ft="), None);
+    }
+
+    #[test]
+    fn detects_by_name_and_contents() {
+        let mut syntax = Syntax::new();
+        syntax.filenames = vec!["ml".to_string(), "Makefile".to_string()];
+        syntax.interpreters = vec!["python".to_string()];
+        assert!(info("src/lib.ml", "").has_name(&syntax));
+        assert!(info("Makefile", "").has_name(&syntax));
+        assert!(!info("Cargo.toml", "").has_name(&syntax));
+        assert!(!info("xml", "").has_name(&syntax));
+        assert!(info("run", "#!/usr/bin/python3.11\n").runs_with(&syntax));
+        assert!(!info("run", "#!/usr/bin/perl\n").runs_with(&syntax));
+        let last = format!("{}# vim: ft=python\n", "x\n".repeat(20));
+        assert_eq!(info("script", &last).modeline, Some("python".to_string()));
+        let middle = format!("{}# vim: ft=python\n{}", "x\n".repeat(10), "x\n".repeat(10));
+        assert_eq!(info("script", &middle).modeline, None);
+    }
+}
@@ -0,0 +1,42 @@
+use std::fmt::Write;
+use termion::{clear, cursor, style};
+
+// One screen of output kept per terminal row, so that it can be compared
+// with what is already on the screen. A row holds everything drawn on it,
+// cursor movements and colors included.
+pub struct Frame {
+    width: u16,
+    rows: Vec<String>,
+}
+
+impl Frame {
+    pub fn new(width: u16, height: u16) -> Frame {
+        Frame {
+            width,
+            rows: vec![String::new(); height as usize],
+        }
+    }
+
+    pub fn row(&mut self, y: usize) -> &mut String {
+        &mut self.rows[y]
+    }
+
+    // Output turning `previous` on screen into this frame. Only rows that
+    // changed are redrawn, all of them after a resize, and the cursor is
+    // hidden meanwhile. Rows are cleared in the `base` style, so that the
+    // theme's background fills them.
+    pub fn diff(&self, previous: &Frame, cursor: (u16, u16), base: &str) -> String {
+        let mut output = format!("{}", cursor::Hide);
+        let full = previous.width != self.width || previous.rows.len() != self.rows.len();
+        if full {
+            let _ = write!(output, "{}", clear::All);
+        }
+        for (y, row) in self.rows.iter().enumerate() {
+            if full || previous.rows[y] != *row {
+                let _ = write!(output, "{}{}{}{}", cursor::Goto(1, y as u16 + 1), base, clear::CurrentLine, row);
+            }
+        }
+        let _ = write!(output, "{}{}{}", style::Reset, cursor::Goto(cursor.0, cursor.1), cursor::Show);
+        output
+    }
+}
@@ -0,0 +1,90 @@
+use buffer::Buffer;
+use command;
+use theme::{Group, Theme};
+use window::Rect;
+
+// Fewest digits the gutter makes room for, so that it does not change
+// width while a short file grows.
+const GUTTER_MIN_DIGITS: usize = 3;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LineNumbers {
+    Off,
+    Absolute,
+    // Distance from the cursor row.
+    Relative,
+    // Relative, with the cursor row's own number in place of 0.
+    Hybrid,
+}
+
+impl LineNumbers {
+    // Name used by `:set number=`.
+    pub fn name(self) -> &'static str {
+        match self {
+            LineNumbers::Off => "off",
+            LineNumbers::Absolute => "absolute",
+            LineNumbers::Relative => "relative",
+            LineNumbers::Hybrid => "hybrid",
+        }
+    }
+
+    // Also takes the flag values, so `number = true` in a config file or
+    // `:set number=yes` means absolute numbers.
+    pub fn from_name(name: &str) -> Option<LineNumbers> {
+        match command::parse_flag(name) {
+            Some(true) => return Some(LineNumbers::Absolute),
+            Some(false) => return Some(LineNumbers::Off),
+            None => {},
+        }
+        [LineNumbers::Off, LineNumbers::Absolute, LineNumbers::Relative, LineNumbers::Hybrid].iter()
+            .cloned()
+            .find(|numbers| numbers.name() == name)
+    }
+}
+
+impl Buffer {
+    // Columns taken by line numbers and the space after them in a window
+    // `width` columns wide. Left out when it would not leave room for text.
+    pub fn gutter_width(&self, width: usize) -> usize {
+        if self.line_numbers == LineNumbers::Off {return 0}
+        let digits = self.text.len().max(1).to_string().len().max(GUTTER_MIN_DIGITS);
+        if digits + 2 > width {0} else {digits + 1}
+    }
+
+    // The part of a window's `rect` left for text once the gutter is drawn.
+    pub fn text_rect(&self, rect: Rect) -> Rect {
+        let gutter = self.gutter_width(rect.width);
+        Rect { left: rect.left + gutter, width: rect.width - gutter, ..rect }
+    }
+
+    // Draws the line number of `row` for a window whose cursor is on `cy`.
+    pub fn draw_line_number(&self, buffer: &mut String, theme: &Theme, row: usize, cy: usize, width: usize) {
+        let number = match self.line_numbers {
+            LineNumbers::Off => return,
+            LineNumbers::Absolute => row + 1,
+            LineNumbers::Hybrid if row == cy => row + 1,
+            LineNumbers::Relative | LineNumbers::Hybrid => row.abs_diff(cy),
+        };
+        buffer.push_str(&format!("{}{:>width$} {}", theme.escape(Group::Gutter), number, theme.escape(Group::Normal), width = width - 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineNumbers;
+
+    #[test]
+    fn flags_name_line_numbers() {
+        assert_eq!(LineNumbers::from_name("on"), Some(LineNumbers::Absolute));
+        assert_eq!(LineNumbers::from_name("true"), Some(LineNumbers::Absolute));
+        assert_eq!(LineNumbers::from_name("off"), Some(LineNumbers::Off));
+        assert_eq!(LineNumbers::from_name("false"), Some(LineNumbers::Off));
+        assert_eq!(LineNumbers::from_name("hybrid"), Some(LineNumbers::Hybrid));
+        assert_eq!(LineNumbers::from_name("yes"), Some(LineNumbers::Absolute));
+        assert_eq!(LineNumbers::from_name("1"), Some(LineNumbers::Absolute));
+        assert_eq!(LineNumbers::from_name("no"), Some(LineNumbers::Off));
+        assert_eq!(LineNumbers::from_name("0"), Some(LineNumbers::Off));
+        assert_eq!(LineNumbers::from_name("relative"), Some(LineNumbers::Relative));
+        assert_eq!(LineNumbers::from_name("2"), None);
+    }
+}
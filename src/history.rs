@@ -0,0 +1,139 @@
+use text::Position;
+
+#[derive(Clone, PartialEq)]
+pub enum Edit {
+    Insert { at: Position, text: String },
+    Delete { at: Position, text: String },
+}
+
+impl Edit {
+    pub fn at(&self) -> Position {
+        match *self {
+            Edit::Insert { at, .. } | Edit::Delete { at, .. } => at,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match *self {
+            Edit::Insert { ref text, .. } | Edit::Delete { ref text, .. } => text,
+        }
+    }
+
+    // Position right after the edited text, once it is present in the buffer.
+    pub fn end(&self) -> Position {
+        let (y, x) = self.at();
+        let text = self.text();
+        match text.rfind('\n') {
+            Some(last) => (y + text.matches('\n').count(), text.len() - last - 1),
+            None => (y, x + text.len()),
+        }
+    }
+
+    pub fn invert(&self) -> Edit {
+        match self.clone() {
+            Edit::Insert { at, text } => Edit::Delete { at, text },
+            Edit::Delete { at, text } => Edit::Insert { at, text },
+        }
+    }
+
+    fn same_kind(&self, other: &Edit) -> bool {
+        matches!((self, other), (&Edit::Insert { .. }, &Edit::Insert { .. }) | (&Edit::Delete { .. }, &Edit::Delete { .. }))
+    }
+}
+
+struct Change {
+    id: usize,
+    edits: Vec<Edit>,
+    before: Position,
+    after: Position,
+}
+
+pub struct History {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+    sealed: bool,
+    grouping: bool,
+    next_id: usize,
+    saved: usize,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            sealed: true,
+            grouping: false,
+            next_id: 1,
+            saved: 0,
+        }
+    }
+
+    pub fn record(&mut self, edit: Edit, before: Position, after: Position) {
+        self.redo.clear();
+        if !self.sealed {
+            if let Some(change) = self.undo.last_mut() {
+                if self.grouping || change.edits.last().is_some_and(|last| last.same_kind(&edit)) {
+                    change.edits.push(edit);
+                    change.after = after;
+                    return
+                }
+            }
+        }
+        self.undo.push(Change {
+            id: self.next_id,
+            edits: vec![edit],
+            before,
+            after,
+        });
+        self.next_id += 1;
+        self.sealed = false;
+    }
+
+    // Close the current change so that the next edit starts a new undo step.
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    // Every edit recorded until `end_group` is undone as a single step.
+    pub fn begin_group(&mut self) {
+        self.sealed = true;
+        self.grouping = true;
+    }
+
+    pub fn end_group(&mut self) {
+        self.grouping = false;
+        self.sealed = true;
+    }
+
+    pub fn undo(&mut self) -> Option<(Vec<Edit>, Position)> {
+        self.sealed = true;
+        let change = self.undo.pop()?;
+        let edits = change.edits.iter().rev().map(Edit::invert).collect();
+        let before = change.before;
+        self.redo.push(change);
+        Some((edits, before))
+    }
+
+    pub fn redo(&mut self) -> Option<(Vec<Edit>, Position)> {
+        self.sealed = true;
+        let change = self.redo.pop()?;
+        let edits = change.edits.clone();
+        let after = change.after;
+        self.undo.push(change);
+        Some((edits, after))
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.sealed = true;
+        self.saved = self.current();
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.current() == self.saved
+    }
+
+    fn current(&self) -> usize {
+        self.undo.last().map_or(0, |change| change.id)
+    }
+}
@@ -1,562 +1,645 @@
-#[macro_use] extern crate lazy_static;
 #[macro_use] extern crate serde_derive;
 extern crate termion;
+extern crate libc;
 extern crate regex;
 extern crate toml;
+extern crate ropey;
+extern crate unicode_segmentation;
+extern crate unicode_width;
+
+mod buffer;
+mod command;
+mod config;
+mod encoding;
+mod error;
+mod file;
+mod filetype;
+mod frame;
+mod gutter;
+mod history;
+mod prompt;
+mod registry;
+mod replace;
+mod search;
+mod swap;
+mod syntax;
+mod terminal;
+mod text;
+mod theme;
+mod window;
+mod wrap;
 
 use std::env;
-use std::fs::File;
 use std::io;
-use std::io::prelude::*;
-use std::io::{BufRead, BufReader, stdin, Stdin, stdout, Stdout, Write};
+use std::io::{BufWriter, Write};
+use std::process;
 use std::rc::Rc;
 use std::time::SystemTime;
-use termion::{clear, color, cursor, style};
-use termion::event::Key;
-use termion::input::{Keys, TermRead};
-use termion::raw::{IntoRawMode, RawTerminal};
-use termion::screen::AlternateScreen;
+use termion::cursor;
+use termion::event::{Event, Key};
+use termion::input::{Events, TermRead};
 use termion::terminal_size;
-use std::fmt;
-use std::error::Error;
+use buffer::Buffer;
+use command::Completion;
+use config::Config;
+use registry::SyntaxRegistry;
+use error::Error;
+use frame::Frame;
 use regex::Regex;
-use toml::value::{Value, Table};
+use search::SearchCase;
 use std::collections::HashMap;
+use syntax::{Highlight, HlState, SyntaxRe};
+use terminal::{Input, Terminal};
+use text::Position;
+use theme::{Group, Theme};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+use window::{Layout, Rect, View, Window};
 
 const KILO_TAB_STOP:usize = 8;
-const KILO_TAB_SPACES:usize = 4;
+const KILO_SOFT_TAB:usize = 4;
 const KILO_QUIT_TIMES:u16 = 2;
+const KILO_MESSAGE_TIMEOUT:u64 = 5;
+const KILO_THEME:&str = "default";
 
+#[derive(Copy, Clone, PartialEq)]
 enum Mode {
     Normal,
     Insert,
     Visual,
 }
 
-
-
 impl Mode {
-    fn process_keypress(&mut self, editor:&mut Editor, c: Key) {
-        match self {
-            Mode::Insert => self.insert(editor, c),
-            Mode::Normal => self.normal(editor, c),
-            Mode::Visual => self.visual(editor, c),
+    fn process_keypress(self, editor: &mut Editor, c: Key) -> Result<i32, i32> {
+        match c {
+            Key::Ctrl('q') => {
+                let dirty = editor.buffers.iter().filter(|buffer| buffer.dirty).count();
+                if dirty > 0 && editor.quit_times > 0 {
+                    let quit = editor.quit_times;
+                    let files = if dirty == 1 {"file has".to_string()} else {format!("{} files have", dirty)};
+                    editor.set_status_message(format!("WARNING!!! {} unsaved changes. Press Ctrl-Q {} more times to quit", files, quit));
+                    editor.quit_times -= 1;
+                    return Ok(1)
+                } else {
+                    return Err(1)
+                }
+            },
+            Key::Ctrl('s') => {editor.save(false);},
+            Key::Ctrl('f') => editor.find(),
+            c => match self {
+                Mode::Insert => self.insert(editor, c),
+                Mode::Normal => self.normal(editor, c),
+                Mode::Visual => self.visual(editor, c),
+            }
         }
+        editor.quit_times = editor.quit_confirmations;
+        Ok(0)
     }
 
-    fn normal(&mut self, editor:&mut Editor, c: Key) {
+    fn motion(editor: &mut Editor, c: Key) -> bool {
+        let pending = editor.pending_key.take();
+        let current = editor.current();
+        let buffer = &mut editor.buffers[current];
         match c {
-            Key::Char('i') => {},
-            _ => {}
+            Key::Char('g') if pending == Some(Key::Char('g')) => buffer.move_to_row(0),
+            Key::Char('g') => editor.pending_key = Some(Key::Char('g')),
+            Key::Char('G') => {
+                let last = buffer.text.len().saturating_sub(1);
+                buffer.move_to_row(last)
+            },
+            Key::Char('h') | Key::Left => buffer.move_cursor(Key::Left),
+            Key::Char('j') | Key::Down => buffer.move_cursor(Key::Down),
+            Key::Char('k') | Key::Up => buffer.move_cursor(Key::Up),
+            Key::Char('l') | Key::Right => buffer.move_cursor(Key::Right),
+            Key::Char('0') | Key::Home => buffer.cx = 0,
+            Key::Char('$') | Key::End => buffer.cx = buffer.row_len(buffer.cy),
+            Key::Char('w') => buffer.word_forward(),
+            Key::Char('b') => buffer.word_backward(),
+            Key::Char('e') => buffer.word_end(),
+            _ => {
+                editor.pending_key = pending;
+                return false
+            }
         }
+        buffer.clamp_cursor();
+        true
     }
 
-    fn insert(&mut self, editor:&mut Editor, c: Key) {
+    fn normal(self, editor: &mut Editor, c: Key) {
+        match editor.pending_key.take() {
+            Some(Key::Char('d')) => {
+                if c == Key::Char('d') {
+                    let buffer = editor.buffer_mut();
+                    let cy = buffer.cy;
+                    buffer.delete_row(cy);
+                }
+                return
+            },
+            Some(Key::Ctrl('w')) => return editor.window_command(c),
+            None if editor.run_binding(c) => return,
+            pending => editor.pending_key = pending,
+        }
+        if Mode::motion(editor, c) {
+            return
+        }
+        editor.pending_key = None;
         match c {
-            Key::Esc => {},
-            Key::Ctrl('q') => {},
-            Key::Ctrl('s') => {},
-            Key::Char('\n') => {},
-            Key::Char(ch) => {},
-            c @ Key::Backspace | c @ Key::Ctrl('h') | c @ Key::Delete => {},
-            Key::Up | Key::Down | Key::Left | Key::Right => {},
+            Key::Char('i') => editor.mode = Mode::Insert,
+            Key::Char('I') => {
+                let buffer = editor.buffer_mut();
+                buffer.cx = buffer.first_non_blank(buffer.cy);
+                editor.mode = Mode::Insert;
+            },
+            Key::Char('a') => {
+                let buffer = editor.buffer_mut();
+                if buffer.row_len(buffer.cy) > 0 {
+                    buffer.cx += 1;
+                }
+                editor.mode = Mode::Insert;
+            },
+            Key::Char('A') => {
+                let buffer = editor.buffer_mut();
+                buffer.cx = buffer.row_len(buffer.cy);
+                editor.mode = Mode::Insert;
+            },
+            Key::Char('o') => editor.open_row(true),
+            Key::Char('O') => editor.open_row(false),
+            Key::Char('u') => {
+                editor.undo();
+                editor.buffer_mut().clamp_cursor();
+            },
+            Key::Ctrl('r') => {
+                editor.redo();
+                editor.buffer_mut().clamp_cursor();
+            },
+            Key::Char('x') => {
+                let buffer = editor.buffer_mut();
+                buffer.delete_char_under_cursor();
+                buffer.clamp_cursor();
+            },
+            Key::Char('d') => editor.pending_key = Some(Key::Char('d')),
+            Key::Ctrl('w') => editor.pending_key = Some(Key::Ctrl('w')),
+            Key::Ctrl('l') => editor.redraw(),
+            Key::Char('/') => editor.find(),
+            Key::Char(':') => editor.command_line(),
+            Key::Char('n') => editor.find_again(true),
+            Key::Char('N') => editor.find_again(false),
+            Key::Char('v') => {
+                editor.visual_start = (editor.buffer().cy, editor.buffer().cx);
+                editor.mode = Mode::Visual;
+            },
             _ => {}
         }
     }
 
-    fn visual(&mut self, editor:&mut Editor, c: Key) {
-
-    }
-}
-
-impl fmt::Display for Mode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let (color, string) = match *self {
-            Mode::Normal => (&color::Yellow as &color::Color , "Normal"),
-            Mode::Insert => (&color::Blue as &color::Color, "Insert"),
-            Mode::Visual => (&color::Red as &color::Color, "Visual"),
-        };
-        write!(f, "{}{} {}", color::Fg(color), string, color::Fg(color::Reset))
-    }
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct Syntax {
-    filetype: String,
-    filenames: Vec<String>,
-    keywords: Vec<String>,
-    important_keywords: String,
-    types: Vec<String>,
-    numbers: String,
-    comments:String
-}
-
-impl Syntax {
-    fn new() -> Syntax {
-        Syntax {
-            filetype: "".to_string(),
-            filenames: vec!(),
-            keywords: vec!(),
-            important_keywords: "".to_string(),
-            types: vec!(),
-            numbers: "".to_string(),
-            comments: "".to_string(),
+    fn insert(self, editor: &mut Editor, c: Key) {
+        match c {
+            Key::Esc => {
+                editor.mode = Mode::Normal;
+                let buffer = editor.buffer_mut();
+                if buffer.cx > 0 {
+                    buffer.cx -= 1;
+                }
+                buffer.clamp_cursor();
+            },
+            Key::Ctrl('z') => editor.undo(),
+            Key::Ctrl('y') => editor.redo(),
+            Key::Ctrl('r') => editor.replace(),
+            Key::Char('\n') => editor.buffer_mut().insert_newline(),
+            Key::Char('\t') => editor.buffer_mut().insert_tab(),
+            Key::Char(ch) => editor.buffer_mut().insert_char(ch),
+            Key::Backspace | Key::Ctrl('h') | Key::Delete => {
+                let buffer = editor.buffer_mut();
+                if c == Key::Delete {
+                    buffer.move_cursor(Key::Right);
+                }
+                buffer.delete_char()
+            },
+            Key::Up | Key::Down | Key::Left | Key::Right => editor.buffer_mut().move_cursor(c),
+            _ => {}
         }
     }
-}
-
-struct SyntaxRe {
-    numbers: Option<Regex>,
-    keywords: Option<Regex>,
-}
 
-impl SyntaxRe {
-    fn new(syntax: &Syntax) -> SyntaxRe {
-        let numbers = if &syntax.numbers == "" {
-            None
-        } else {
-            Some(Regex::new(&syntax.numbers).unwrap())
-        };
-        let keywords = if syntax.keywords.len()>0 {
-            let mut all = syntax.keywords.join("|");
-            all.insert_str(0, r"\b(");
-            all.push_str(r")\b");
-            Some(Regex::new(&all).unwrap())
-        } else {
-            None
-        };
-        SyntaxRe {
-            numbers,
-            keywords,
+    fn visual(self, editor: &mut Editor, c: Key) {
+        if Mode::motion(editor, c) {
+            return
+        }
+        editor.pending_key = None;
+        match c {
+            Key::Esc | Key::Char('v') => editor.mode = Mode::Normal,
+            Key::Char('d') | Key::Char('x') => {
+                if let Some((start, end)) = editor.selection() {
+                    editor.buffer_mut().delete_range(start, end);
+                }
+                editor.mode = Mode::Normal;
+                editor.buffer_mut().clamp_cursor();
+            },
+            _ => {}
         }
     }
 }
 
-#[derive(PartialEq)]
-#[derive(Copy, Clone)]
-enum Highlight {
-    Normal,
-    Number,
-    Type,
-    Keyword,
-    Match,
-}
-
-impl Highlight {
-    fn to_color(& self) -> color::Fg<&color::Color> {
+impl Mode {
+    fn name(self) -> &'static str {
         match self {
-            &Highlight::Normal => color::Fg(&color::Reset),
-            &Highlight::Number => color::Fg(&color::Red),
-            &Highlight::Type => color::Fg(&color::Yellow),
-            &Highlight::Keyword => color::Fg(&color::Magenta),
-            &Highlight::Match => color::Fg(&color::Black),
+            Mode::Normal => "Normal",
+            Mode::Insert => "Insert",
+            Mode::Visual => "Visual",
         }
     }
 
-    fn to_background(& self) -> color::Bg<&color::Color> {
+    fn group(self) -> Group {
         match self {
-            &Highlight::Normal => color::Bg(&color::Reset),
-            &Highlight::Match => color::Bg(&color::Yellow),
-            _ => color::Bg(&color::Reset),
+            Mode::Normal => Group::ModeNormal,
+            Mode::Insert => Group::ModeInsert,
+            Mode::Visual => Group::ModeVisual,
         }
     }
 }
 
+
+// A text row as drawn, cached by its buffer. `version`, `state` and
+// `tab_stop` are what it was rendered with and tell when it is stale.
 struct Row {
     chars: String,
     render: String,
+    offsets: Vec<(usize, usize)>,
     highlight: Vec<Highlight>,
-    syntax: Rc<SyntaxRe>
+    syntax: Rc<SyntaxRe>,
+    state: HlState,
+    next_state: HlState,
+    tab_stop: usize,
+    version: usize,
 }
 
 impl Row {
-    fn new(chars: String, syntax: Rc<SyntaxRe>) -> Row {
+    fn new(chars: String, syntax: Rc<SyntaxRe>, state: HlState, tab_stop: usize, version: usize) -> Row {
         let render = chars.clone();
         let mut row = Row {
             chars,
             render,
+            offsets: Vec::new(),
             highlight: Vec::new(),
             syntax,
+            state,
+            next_state: state,
+            tab_stop,
+            version,
         };
         row.update();
         row
     }
 
-    fn add_syntax(&mut self, syntax: Rc<SyntaxRe>) {
-        self.syntax = syntax;
-    }
-
     fn update(&mut self) {
 
         self.render.clear();
+        self.offsets.clear();
         let mut idx = 0;
 
-        for character in self.chars.chars() {
-            // let character = ch.next().unwrap();
-            if character == '\t' {
+        for (raw, grapheme) in self.chars.grapheme_indices(true) {
+            self.offsets.push((raw, self.render.len()));
+            if grapheme == "\t" {
                 self.render.push(' ');
                 idx += 1;
-                while idx % KILO_TAB_STOP != 0 {
+                while idx % self.tab_stop != 0 {
                     self.render.push(' ');
                     idx += 1;
                 }
-            } else {
-                self.render.push(character);
+            } else if grapheme.chars().any(char::is_control) {
+                self.render.push('?');
                 idx += 1;
+            } else {
+                self.render.push_str(grapheme);
+                idx += grapheme.width();
             }
         }
+        self.offsets.push((self.chars.len(), self.render.len()));
         self.update_syntax()
     }
 
-    fn update_syntax(&mut self) {
-        lazy_static! {
-            static ref TYPE: Regex = Regex::new(r"[^:]:\s?(&?\w+)").unwrap();
-            static ref KEYWORD: Regex = Regex::new(r"\b(in|fn|match|if|else|for|loop|while|let|impl|use)\b").unwrap();
-        }
-        self.highlight.clear();
-        self.highlight.resize(self.render.len(), Highlight::Normal);
-        
-        if let Some(number_re) = &self.syntax.numbers {
-            for capture in number_re.captures_iter(&self.render) {
-                let mat = capture.get(1).unwrap();
-                for hl in mat.start()..mat.end() {
-                    self.highlight[hl] = Highlight::Number;
-                }
-            }
+    fn render_offset(&self, raw: usize) -> usize {
+        match self.offsets.binary_search_by_key(&raw, |&(chars, _)| chars) {
+            Ok(i) => self.offsets[i].1,
+            Err(i) => self.offsets[i.min(self.offsets.len() - 1)].1,
         }
+    }
 
-        for capture in TYPE.captures_iter(&self.render) {
-            let mat = capture.get(1).unwrap();
-            for hl in mat.start()..mat.end() {
-                self.highlight[hl] = Highlight::Type;
-            }
-        }
+    // Byte ranges of `render` matched by `re`, drawn over the cached highlight.
+    fn matches(&self, re: &Regex) -> Vec<(usize, usize)> {
+        re.find_iter(&self.chars)
+            .map(|m| (self.render_offset(m.start()), self.render_offset(m.end())))
+            .collect()
+    }
 
-        if let Some(keyword_re) = &self.syntax.keywords {
-            for capture in keyword_re.captures_iter(&self.render) {
-                let mat = capture.get(1).unwrap();
-                for hl in mat.start()..mat.end() {
-                    self.highlight[hl] = Highlight::Keyword;
-                }
-            }
-        }
+    fn update_syntax(&mut self) {
+        self.highlight.clear();
+        self.highlight.resize(self.render.len(), Highlight::Normal);
+        self.next_state = self.syntax.highlight(&self.render, self.state, &mut self.highlight);
     }
 
-    fn draw(&mut self, mut buffer: String, coloff: usize, len: usize) -> String {
+    fn draw(&self, buffer: &mut String, theme: &Theme, coloff: usize, len: usize, selection: Option<(usize, usize)>, matches: &[(usize, usize)]) {
         let mut current = Highlight::Normal;
-        for (character, highlight) in self.render.chars().zip(self.highlight.clone()).skip(coloff).take(len) {
-            match highlight {
-                hl if hl == current => buffer.push(character),
-                hl => {
-                    buffer.push_str(format!("{}{}{}", hl.to_color(), hl.to_background(), character).as_str());
-                    current = hl
+        let mut col = 0;
+        for (idx, grapheme) in self.render.grapheme_indices(true) {
+            let width = grapheme.width();
+            if col < coloff {
+                // A wide character cut in half by the left edge leaves blanks.
+                for _ in coloff..(col + width).min(coloff + len) {
+                    buffer.push(' ');
                 }
+                col += width;
+                continue
+            }
+            if col + width > coloff + len {break}
+            let highlight = match selection {
+                Some((start, end)) if col >= start && col < end => Highlight::Selection,
+                _ if matches.iter().any(|&(start, end)| idx >= start && idx < end) => Highlight::Match,
+                _ => self.highlight.get(idx).cloned().unwrap_or(Highlight::Normal),
+            };
+            if highlight != current {
+                buffer.push_str(theme.escape(highlight.group()));
+                current = highlight;
             }
+            buffer.push_str(grapheme);
+            col += width;
         }
-        buffer.push_str(format!("{}{}", Highlight::Normal.to_color(), Highlight::Normal.to_background()).as_str());
-        buffer
+        buffer.push_str(theme.escape(Group::Normal));
     }
+}
 
-    fn insert_char(&mut self, mut at: usize, c: char) {
-        if at > self.chars.len() {
-            at = self.chars.len();
+// The start of `s` that fits in `width` columns, and how many it takes.
+fn truncate(s: &str, width: usize) -> (&str, usize) {
+    let mut used = 0;
+    for (i, grapheme) in s.grapheme_indices(true) {
+        let grapheme_width = grapheme_width(grapheme, used, 1);
+        if used + grapheme_width > width {
+            return (&s[..i], used)
         }
-
-        self.chars.insert(at, c);
-        self.update();
-    }
-
-    fn append_string(&mut self, line: String) {
-        self.chars += &line;
-        self.update();
+        used += grapheme_width;
     }
+    (s, used)
+}
 
-    fn delete_char(&mut self, at: usize) -> usize {
-        if at >= self.chars.len() {return 0}
-        let spaces = KILO_TAB_SPACES;
-        let deleted: usize;
-        if at+1 >= spaces && &self.chars[at+1-spaces..at+1]=="    " {
-            for i in at+1-spaces..at+1 {
-                self.chars.remove(at+1-spaces);
-            }
-            deleted = spaces;
-        } else {
-            self.chars.remove(at);
-            deleted = 1;
-        }
-        self.update();
-        return deleted;
+fn grapheme_width(grapheme: &str, rx: usize, tab_stop: usize) -> usize {
+    if grapheme == "\t" {
+        tab_stop - rx % tab_stop
+    } else if grapheme.chars().any(char::is_control) {
+        1
+    } else {
+        grapheme.width()
     }
-
 }
 
 struct Editor {
-    cx: usize,
-    cy: usize,
-    rx: usize,
-    rowoff: usize,
-    coloff: usize,
     screenrows: u16,
     screencols: u16,
-    rows: Vec<Row>,
+    buffers: Vec<Buffer>,
+    windows: Vec<Window>,
+    layout: Layout,
+    active: usize,
     mode: Mode,
-    dirty: bool,
+    pending_key: Option<Key>,
+    visual_start: (usize, usize),
     quit_times: u16,
-    filename: Option<String>,
-    syntax: Rc<Syntax>,
-    syntax_re: Rc<SyntaxRe>,
+    // Settings from the config file, or changed with `:set`.
+    config: Config,
+    syntaxes: SyntaxRegistry,
+    quit_confirmations: u16,
+    message_timeout: u64,
+    theme: String,
+    colors: Theme,
+    quit: bool,
     status_message: Option<(String, SystemTime)>,
-    screen: AlternateScreen<RawTerminal<Stdout>>,
-    stdin: Keys<Stdin>,
+    prompt_histories: HashMap<&'static str, Vec<String>>,
+    search: Option<Regex>,
+    search_case: SearchCase,
+    search_origin: Position,
+    completion: Option<Completion>,
+    screen: Terminal,
+    // Read as events: termion's key iterator swallows read errors.
+    stdin: Events<Input>,
+    // Why reading input failed; the editor exits keeping its swap files.
+    failure: Option<Error>,
+    // What the last draw left on the screen.
+    frame: Frame,
 }
 
 impl Editor {
-    fn new() -> Editor {
-        let stdout = stdout().into_raw_mode().unwrap();
-        let screen = AlternateScreen::from(stdout);
-        let (screencols, screenrows) = terminal_size().unwrap();
-        let syntax = Syntax::new();
-        Editor {
-            cx:0,
-            cy:0,
-            rx:0,
-            rowoff:0,
-            coloff:0,
-            screenrows:screenrows-2,
+    fn new() -> io::Result<Editor> {
+        let (screencols, screenrows) = terminal_size()?;
+        let screen = Terminal::new()?;
+        Ok(Editor {
+            screenrows: screenrows.saturating_sub(1),
             screencols,
-            rows:Vec::new(),
-            mode:Mode::Insert,
+            buffers: vec![Buffer::new()],
+            windows: vec![Window { buffer: 0, view: View { cx: 0, cy: 0, rowoff: 0, coloff: 0 } }],
+            layout: Layout::Window(0),
+            active: 0,
+            mode:Mode::Normal,
+            pending_key:None,
+            visual_start:(0, 0),
             screen,
-            dirty:false,
             quit_times: KILO_QUIT_TIMES,
-            filename:None,
-            syntax:Rc::new(syntax.clone()),
-            syntax_re:Rc::new(SyntaxRe::new(&syntax)),
+            config: Config::new(),
+            syntaxes: SyntaxRegistry::new(),
+            quit_confirmations: KILO_QUIT_TIMES,
+            message_timeout: KILO_MESSAGE_TIMEOUT,
+            theme: KILO_THEME.to_string(),
+            colors: Theme::new(),
+            quit: false,
             status_message: None,
-            stdin:stdin().keys(),
-        }
+            prompt_histories: HashMap::new(),
+            search: None,
+            search_case: SearchCase::Smart,
+            search_origin: (0, 0),
+            completion: None,
+            stdin: Input.events(),
+            failure: None,
+            frame: Frame::new(0, 0),
+        })
     }
 
-    fn read_file(&mut self, filename: String) {
-        self.filename = Some(filename.clone());
-        let syntax = Editor::get_syntax(&filename);
-        self.syntax = Rc::clone(&syntax);
-        self.syntax_re = Rc::new(SyntaxRe::new(&syntax));
-        let file = File::open(&filename);
-        if let Err(err) = file {
-            self.set_status_message(format!("{} [New file]", filename));
-            return
-        }
-        let file = file.unwrap();
-        let buf_reader = BufReader::new(file);
-
-        let lines = buf_reader.lines();
-        // let syntax = Rc::clone(&self.syntax);
-        for line in lines {
-            let line = line.unwrap();
-            let s = Rc::clone(&self.syntax_re);
-            self.rows.push(Row::new(line, s));
-        }
+    // Index of the buffer shown in the active window.
+    fn current(&self) -> usize {
+        self.windows[self.active].buffer
     }
 
-    fn get_syntax(filename: &str) -> Rc<Syntax> {
-        let mut f = File::open("syntax.toml");
-        let mut contents = String::new();
-        if let Ok(mut f) = f {
-            f.read_to_string(&mut contents).unwrap();
-        } else {
-            contents = String::from_utf8_lossy(include_bytes!("syntax.toml")).to_string();
-        }
-        let value = contents.parse::<Value>().unwrap();
-        if let Some(value) = value.get("syntax") {
-            for syntax in value.as_array().unwrap() {
-                let s = syntax.clone().try_into::<Syntax>().unwrap();
-                for ending in s.filenames.clone() {
-                    if filename.ends_with(&ending) {
-                        return Rc::new(s);
-                    }
-                }
-            }
-        }
-        return Rc::new(Syntax::new());
+    fn buffer(&self) -> &Buffer {
+        &self.buffers[self.current()]
     }
 
-    fn rows_to_string(&self) -> String {
-        let mut buffer = String::new();
-        /*self.rows.foreach(|row| {
-           buffer += &row.chars
-        });*/
-        for row in &self.rows {
-            buffer += &row.chars;
-            buffer.push('\n');
-        }
-        buffer
+    fn buffer_mut(&mut self) -> &mut Buffer {
+        let current = self.current();
+        &mut self.buffers[current]
     }
 
-    fn save(&mut self, save_as: bool) {
-        if let None = self.filename.clone() {
-            self.filename = self.prompt("Save as: ".to_string(), None);
-        } else if save_as {
-            self.filename = self.prompt("Save as: ".to_string(), None);
+    // Opens `filename` in a new buffer, or switches to the buffer already
+    // holding it. The empty buffer the editor starts with is reused.
+    fn open_buffer(&mut self, filename: String) {
+        if let Some(index) = self.buffers.iter().position(|buffer| buffer.filename.as_ref() == Some(&filename)) {
+            return self.switch_buffer(index)
         }
-
-        if let Some(filename) = self.filename.clone() {
-            let mut file = match File::create(&filename) {
-                Ok(file) => file,
-                Err(_err) => {
-                    self.set_status_message("Can't save, I/O error".to_string());
-                    return;
-                }
-            };
-            let buffer = self.rows_to_string();
-            file.write_all(buffer.as_bytes()).expect("Unable to write data");
-            self.dirty = false;
-            self.set_status_message(format!("{} bytes written in \"{}\"", buffer.len(), filename));
+        let mut buffer = Buffer::new();
+        let exists = match buffer.read_file(filename.clone()) {
+            Ok(exists) => exists,
+            Err(err) => return self.set_status_message(format!("Can't open \"{}\": {}", filename, err)),
+        };
+        let (syntax, syntax_re) = self.syntaxes.detect(&filename, &buffer.text);
+        buffer.set_syntax(syntax, syntax_re);
+        if self.buffer().is_scratch() {
+            *self.buffer_mut() = buffer;
         } else {
-            self.set_status_message("Not saved :(".to_string());
+            self.buffers.push(buffer);
+            self.windows[self.active].buffer = self.buffers.len() - 1;
+        }
+        self.mode = Mode::Normal;
+        let binary = self.buffer().read_only;
+        let current = self.current();
+        let configured = self.configure_buffer(current);
+        if !exists {
+            self.set_status_message(format!("{} [New file]", filename));
+        } else if binary {
+            self.set_status_message(format!("\"{}\" looks binary, opened read-only", filename));
+        }
+        if let Err(message) = configured {
+            self.set_status_message(message);
+        }
+        if !binary {
+            self.check_swap();
         }
     }
 
-    fn find_callback(editor: &mut Self, query: &str, key: Key) {
-        let mut direction: isize = 1;
-        if key == Key::Char('\n') || key == Key::Esc {
-            return;
-        } else if key == Key::Down {
-            direction = 1;
-        } else if key == Key::Up {
-            direction = -1;
-        } else {
-            direction = 1;
-        }
-        if query != "" {
-            let mut current = editor.cy;
-            for i in 0..editor.rows.len() {
-                if current == 0 && direction == -1 {current = editor.rows.len();}
-                current = (current as isize + direction) as usize;
-                if current == editor.rows.len() {current = 0;}
-                eprintln!("{}", current);
-                if let Some(index) = editor.rows[current].chars.find(&query) {
-                    editor.cy = current;
-                    editor.cx = index;
-                    editor.rowoff = editor.rows.len();
-
-                    /*if let Some(index) = editor.rows[current].render.find(&query) {
-                        for j in index..index+&query.len() {
-                            editor.rows[current].highlight[j] = Highlight::Match;
-                        }
-                    }*/
-                    break;
-                }
+    // Reads the current buffer's file again, dropping its changes and history.
+    fn reload_buffer(&mut self) {
+        if let Some(filename) = self.buffer().filename.clone() {
+            let mut buffer = Buffer::new();
+            if let Err(err) = buffer.read_file(filename.clone()) {
+                return self.set_status_message(format!("Can't reload \"{}\": {}", filename, err))
+            }
+            let (syntax, syntax_re) = self.syntaxes.detect(&filename, &buffer.text);
+            buffer.set_syntax(syntax, syntax_re);
+            *self.buffer_mut() = buffer;
+            self.mode = Mode::Normal;
+            let current = self.current();
+            if let Err(message) = self.configure_buffer(current) {
+                self.set_status_message(message);
             }
         }
     }
 
-    fn find(&mut self) {
-        let saved_cx = self.cx;
-        let saved_cy = self.cy;
-        let saved_coloff = self.coloff;
-        let saved_rowoff = self.rowoff;
-        self.screencols = self.screencols/2;
-
-        let query  = self.prompt("Search: ".to_string(), Some(Editor::find_callback));
-
-        self.screencols = self.screencols*2;
-
-        if query == None {
-            self.cx = saved_cx;
-            self.cy = saved_cy;
-            self.coloff = saved_coloff;
-            self.rowoff = saved_rowoff;
-        }
+    fn switch_buffer(&mut self, index: usize) {
+        self.windows[self.active].buffer = index;
+        self.mode = Mode::Normal;
+        self.pending_key = None;
+        let buffer = self.buffer();
+        let modified = if buffer.dirty {" [Modified]"} else {""};
+        let message = format!("\"{}\" {} lines{}", buffer.name(), buffer.text.len(), modified);
+        self.set_status_message(message);
     }
 
-    fn insert_char(&mut self, c: char) {
-        self.dirty = true;
-        if self.cy == self.rows.len() {
-            self.rows.push(Row::new("".to_string(), Rc::clone(&self.syntax_re)));
+    fn save(&mut self, save_as: bool) -> bool {
+        if self.buffer().read_only && !save_as {
+            self.set_status_message("File is read-only (add ! to override)".to_string());
+            return false
+        }
+        if self.buffer().filename.is_none() || save_as {
+            self.buffer_mut().filename = self.prompt("Save as: ".to_string(), None, None);
         }
-        // eprintln!("insert at: {}, {}", self.cx, self.cy);
-        self.rows[self.cy].insert_char(self.cx, c);
-        self.cx += 1;
-    }
-
-    fn delete_char(&mut self) {
-        if self.cy == self.rows.len() {return}
-        if self.cx == 0 && self.cy == 0 {return}
-
-        self.dirty = true;
 
-        if self.cx > 0 {
-            let deleted = self.rows[self.cy].delete_char(self.cx - 1);
-            self.cx -= deleted;
+        if let Some(filename) = self.buffer().filename.clone() {
+            if self.write_file(&filename) {
+                self.buffer_mut().mark_saved();
+                return true
+            }
         } else {
-            self.cx = self.rows[self.cy - 1].chars.len();
-            let previous_row = self.rows.remove(self.cy).chars;
-            self.rows[self.cy - 1].append_string(previous_row);
-            self.cy -= 1;
+            self.set_status_message("Not saved :(".to_string());
+        }
+        false
+    }
+
+    fn write_file(&mut self, filename: &str) -> bool {
+        let result = file::save(filename, |file| {
+            let mut writer = BufWriter::new(file);
+            let buffer = self.buffer();
+            let written = buffer.text.write_to(&mut writer, buffer.format)?;
+            writer.flush()?;
+            Ok(written)
+        });
+        match result {
+            Ok((written, method)) => {
+                let how = if method == file::SaveMethod::InPlace {" (in place, not atomic)"} else {""};
+                self.set_status_message(format!("{} bytes written in \"{}\"{}", written, filename, how));
+                true
+            },
+            Err(err) => {
+                self.set_status_message(format!("Can't save, I/O error: {}", err));
+                false
+            },
         }
     }
 
-    fn insert_row(&mut self, at: usize, s: String) {
-        if at > self.rows.len() {return}
-
-        let row= Row::new(s, Rc::clone(&self.syntax_re));
-        self.rows.insert(at, row);
+    fn undo(&mut self) {
+        let buffer = self.buffer_mut();
+        match buffer.history.undo() {
+            Some((edits, cursor)) => buffer.replay(edits, cursor),
+            None => self.set_status_message("Already at oldest change".to_string()),
+        }
     }
 
-    fn insert_newline(&mut self) {
-        self.dirty = true;
-        if self.cx == 0 {
-            let cy = self.cy;
-            self.insert_row(cy, "".to_string());
-        } else {
-            let (cx, cy) = (self.cx, self.cy);
-            let next_row = self.rows[self.cy].chars.split_off(cx);
-            self.rows[self.cy].update();
-            self.insert_row(cy + 1, next_row);
-
+    fn redo(&mut self) {
+        let buffer = self.buffer_mut();
+        match buffer.history.redo() {
+            Some((edits, cursor)) => buffer.replay(edits, cursor),
+            None => self.set_status_message("Already at newest change".to_string()),
         }
-        self.cy += 1;
-        self.cx = 0;
     }
 
-    fn write(&mut self, string: &str) {
-        write!(self.screen, "{}{}{}",clear::All, cursor::Goto(1,1), string).unwrap();
-        self.screen.flush().unwrap();
+    fn write(&mut self, string: &str) -> io::Result<()> {
+        write!(self.screen, "{}", string)?;
+        self.screen.flush()
     }
 
-    fn status_bar(&mut self, mut buffer: String) -> String {
-        buffer.push_str(format!("{}", style::Invert).as_str());
-        let filename = self.filename.clone().unwrap_or("[None]".to_string());
-        let modified = if self.dirty {"(modified)"} else {""};
-        let status = format!("{} {} - {} lines {}", self.mode, filename, self.rows.len(), modified);
-        let rstatus = format!("[{}] {}/{} ", self.syntax.filetype, self.cy+1, self.rows.len());
-        let mut status_size = status.len();
-        let rstatus_size = rstatus.len();
-        status_size = if status_size as u16 > self.screencols {self.screencols as usize} else {status_size};
-        buffer = buffer + &status[..status_size];
-
-        for _ in 0..self.screencols as i16-status_size as i16 - rstatus_size as i16 + 14 {
-            buffer.push(' ');
-        }
-        if self.screencols as i16 - status_size as i16 - rstatus_size as i16 >= 0 {
-            buffer = buffer + &rstatus;
+    fn status_bar(&self, buffer: &mut String, id: usize, width: usize, cy: usize) {
+        let active = id == self.active;
+        let current = &self.buffers[self.windows[id].buffer];
+        let group = if active {Group::StatusBar} else {Group::StatusBarInactive};
+        buffer.push_str(self.colors.escape(group));
+        let filename = current.name();
+        let modified = match (current.dirty, current.read_only) {
+            (true, _) => "(modified)",
+            (false, true) => "(read-only)",
+            (false, false) => "",
+        };
+        let number = if self.buffers.len() > 1 {format!(" [{}/{}]", self.windows[id].buffer + 1, self.buffers.len())} else {String::new()};
+        let status = format!("{}{} - {} lines {}", filename, number, current.text.len(), modified);
+        let rstatus = format!("[{}] {} {}/{} ", current.syntax.filetype, current.format.label(), cy+1, current.text.len());
+        // Only the active window shows the mode, whose colors take no room.
+        let mode_size = if active && self.mode.name().len() < width {self.mode.name().len() + 1} else {0};
+        if mode_size > 0 {
+            buffer.push_str(&format!("{}{} {}", self.colors.escape(self.mode.group()), self.mode.name(), self.colors.escape(group)));
+        }
+        let (status, status_size) = truncate(&status, width - mode_size);
+        *buffer += status;
+        let used = mode_size + status_size;
+        if used + rstatus.width() <= width {
+            for _ in used..width - rstatus.width() {
+                buffer.push(' ');
+            }
+            *buffer += &rstatus;
+        } else {
+            for _ in used..width {
+                buffer.push(' ');
+            }
         }
-        buffer.push_str(format!("{}\r\n", style::NoInvert).as_str());
-        buffer
+        buffer.push_str(self.colors.escape(Group::Normal));
     }
 
-    fn message_bar(&mut self, buffer: String) -> String {
-        if self.status_message.is_some() {
-            let (message, time) = self.status_message.clone().unwrap();
-            let mut message_len = message.len();
-            message_len = if message_len > self.screencols as usize {self.screencols as usize} else {message_len};
+    fn message_bar(&self, buffer: &mut String) {
+        if let Some((ref message, time)) = self.status_message {
             match time.elapsed() {
-                Ok(elapsed) if elapsed.as_secs() < 5 => buffer + &message[..message_len],
-                Ok(_) | Err(_) => buffer
+                Ok(elapsed) if elapsed.as_secs() < self.message_timeout => *buffer += truncate(message, self.screencols as usize).0,
+                Ok(_) | Err(_) => {},
             }
-        } else {
-            buffer
         }
     }
 
@@ -564,49 +647,77 @@ impl Editor {
         self.status_message = Some((message, SystemTime::now()));
     }
 
-    fn prompt(&mut self, message: String, callback: Option<fn(&mut Self, &str, Key)>) -> Option<String> {
-        let mut buffer = String::new();
-        loop {
-            self.set_status_message(format!("{}{}", message, buffer));
-            self.draw();
-            let c = self.stdin.next().unwrap().unwrap();
-            match c {
-                Key::Delete | Key::Backspace => {buffer.pop();()},
-                Key::Esc => {
-                    self.set_status_message("".to_string());
-                    if let Some(callback) = callback {
-                        callback(self, &buffer, c);
-                    }
-                    return None;
-                },
-                Key::Char('\n') => {
-                    if buffer.len() != 0 {
-                        self.set_status_message("".to_string());
-                        if let Some(callback) = callback {
-                            callback(self, &buffer, c);
-                        }
-                        return Some(buffer);
-                    }
-                },
-                Key::Char(c) => buffer.push(c),
-                _ => (),
+    fn draw(&mut self) {
+        let mut frame = Frame::new(self.screencols, self.screenrows + 1);
+        self.scroll_cursor();
+        self.scroll_windows();
+        let (windows, borders) = self.window_rects();
+        // A screen too small for every window squeezes some of them out.
+        for &(id, rect) in windows.iter().filter(|&&(_, rect)| rect.height > 0 && rect.width > 0) {
+            self.draw_window(&mut frame, id, rect);
+        }
+        // Only rows on screen stay rendered.
+        let shown: Vec<(usize, usize, usize)> = windows.iter().map(|&(id, rect)| {
+            let index = self.windows[id].buffer;
+            let view = if id == self.active {self.buffers[index].view()} else {self.windows[id].view};
+            (index, view.rowoff, rect.height)
+        }).collect();
+        for (index, buffer) in self.buffers.iter_mut().enumerate() {
+            let visible: Vec<(usize, usize)> = shown.iter()
+                .filter(|&&(shown, _, _)| shown == index)
+                .map(|&(_, top, height)| (top, height))
+                .collect();
+            buffer.trim_rows(&visible);
+        }
+        for border in borders {
+            for y in border.top..border.top + border.height {
+                frame.row(y).push_str(format!("{}\u{2502}", cursor::Goto(border.left as u16 + 1, y as u16 + 1)).as_str());
             }
-            if let Some(callback) = callback {
-                callback(self, &buffer, c);
+        }
+        self.message_bar(frame.row(self.screenrows as usize));
+        let rect = self.window_rect(self.active);
+        let current = self.buffer_mut();
+        let rect = current.text_rect(rect);
+        let (y, x) = current.cursor_on_screen(rect);
+        let cursor = ((rect.left + x + 1) as u16, (rect.top + y + 1) as u16);
+
+        let output = frame.diff(&self.frame, cursor, self.colors.escape(Group::Normal));
+        self.frame = frame;
+        // Like a failed read, a terminal that can't be written to ends the
+        // editor keeping its swap files.
+        if let Err(err) = self.write(output.as_str()) {
+            self.failure = Some(Error::Io(err));
+            self.quit = true;
+        }
+    }
+
+    // Forgets what is on the screen, so that the next draw repaints it all.
+    fn redraw(&mut self) {
+        self.frame = Frame::new(0, 0);
+    }
+
+    fn draw_window(&mut self, frame: &mut Frame, id: usize, rect: Rect) {
+        let index = self.windows[id].buffer;
+        let view = if id == self.active {self.buffers[index].view()} else {self.windows[id].view};
+        let text_rows = rect.height.saturating_sub(1);
+        let (mut file_row, mut line, mut starts) = (view.rowoff, 0, Vec::new());
+        // A wrapped top row can be shown from one of its later screen lines.
+        if self.buffers[index].wrap {
+            let current = &mut self.buffers[index];
+            let width = rect.width - current.gutter_width(rect.width);
+            line = current.skipped_lines(view, width);
+            if line > 0 {
+                starts = current.screen_lines(file_row, width);
             }
         }
-    }
-
-    fn draw(&mut self) {
-        let mut buffer = String::with_capacity(((self.screencols) * self.screenrows) as usize);
-        self.scroll_cursor();
-        for y in 0..self.screenrows as usize {
-            let file_row = y + self.rowoff;
-            if file_row >= self.rows.len() {
-                if self.rows.len() == 0 && y == self.screenrows as usize / 3 {
-                    let welcome = "Kilo editor for Rust -- version 0.0.1";
-                    let welcome_len = welcome.len() as u16;
-                    let mut padding = (self.screencols - welcome_len) / 2;
+        for y in 0..text_rows {
+            let buffer = frame.row(rect.top + y);
+            buffer.push_str(format!("{}", cursor::Goto(rect.left as u16 + 1, (rect.top + y) as u16 + 1)).as_str());
+            let rows = self.buffers[index].text.len();
+            if file_row >= rows {
+                let welcome = "Kilo editor for Rust -- version 0.0.1";
+                if rows == 0 && y == text_rows / 3 && welcome.len() < rect.width {
+                    let mut padding = (rect.width - welcome.len()) / 2;
                     if padding > 0 {
                         buffer.push('~');
                         padding -= 1;
@@ -616,160 +727,206 @@ impl Editor {
                     }
                     buffer.push_str(welcome);
                 } else {
-                    buffer.push_str(format!("{}", style::Bold).as_str());
+                    buffer.push_str(self.colors.escape(Group::NonText));
                     buffer.push('~');
-                    buffer.push_str(format!("{}", style::Reset).as_str());
+                    buffer.push_str(self.colors.escape(Group::Normal));
                 }
             } else {
-                let mut len =
-                    if self.rows[file_row as usize].render.len() < self.coloff as usize {
-                        0
-                    } else {
-                        self.rows[file_row as usize].render.len() - self.coloff as usize
-                    };
-
-                if len > self.screencols as usize {len = self.screencols as usize}
-                if len > 0 {
-                    buffer = self.rows[file_row].draw(buffer, self.coloff, len);
-                    // let len_diff = self.rows[file_row].render_hl.len() - self.rows[file_row].render.len();
-                    // eprintln!("len_diff: {}", len_diff);
-                    // let render = &self.rows[file_row]
-                    //     .render_hl[self.coloff as usize..(self.coloff as usize + len + len_diff) as usize];
-                    // buffer.push_str(render); // &self.rows[file_row].render_hl);
+                let selection = if id == self.active {self.row_selection(file_row)} else {None};
+                let current = &mut self.buffers[index];
+                let gutter = current.gutter_width(rect.width);
+                let width = rect.width - gutter;
+                if line == 0 {
+                    starts = current.screen_lines(file_row, width);
                 }
-            }
-            buffer.push_str("\r\n");
-        }
-        buffer = self.status_bar(buffer);
-        buffer = self.message_bar(buffer);
-        buffer.push_str(
-            format!("{}", cursor::Goto(
-                (self.rx - self.coloff + 1) as u16, (self.cy-self.rowoff+1) as u16)).as_str());
-
-        self.write(buffer.as_str());
-        // eprintln!("cursor: {}, {}", self.cx, self.cy);
-    }
-
-    fn process_keypress(&mut self) -> Result<i32, i32> {
-        let c = self.stdin.next().unwrap().unwrap();
-        match c {
-            Key::Ctrl('q') => {
-                if self.dirty && self.quit_times > 0 {
-                    let quit = self.quit_times;
-                    self.set_status_message(format!("WARNING!!! file has unsaved changes. Press Ctrl-Q {} more times to quit", quit));
-                    self.quit_times -= 1;
-                    return Ok(1)
-                } else {
-                    return Err(1)
+                if gutter > 0 && line == 0 {
+                    current.draw_line_number(buffer, &self.colors, file_row, view.cy, gutter);
+                } else if gutter > 0 {
+                    buffer.push_str(&" ".repeat(gutter));
                 }
-            },
-            Key::Ctrl('s') => self.save(false),
-            Key::Ctrl('f') => self.find(),
-            Key::Char('\n') => self.insert_newline(),
-            Key::Char('\t') => for _ in 0..KILO_TAB_SPACES {self.insert_char(' ')},
-            Key::Char(ch) => self.insert_char(ch),
-            Key::Backspace | Key::Ctrl('h') | Key::Delete => {
-                if c == Key::Delete {
-                    self.move_cursor(Key::Right);
+                let (coloff, len) = if !current.wrap {
+                    (view.coloff, width)
+                } else if line == 0 {
+                    (0, starts.get(1).map_or(width, |&next| next))
+                } else {
+                    let indent = current.showbreak_width(width);
+                    if indent > 0 {
+                        buffer.push_str(&format!("{}{}{}", self.colors.escape(Group::NonText), current.showbreak, self.colors.escape(Group::Normal)));
+                    }
+                    let room = width - indent;
+                    (starts[line], starts.get(line + 1).map_or(room, |&next| next - starts[line]))
+                };
+                let row = current.row(file_row);
+                let matches = match self.search {
+                    Some(ref re) => row.matches(re),
+                    None => Vec::new(),
+                };
+                row.draw(buffer, &self.colors, coloff, len, selection, &matches);
+                line += 1;
+                if line == starts.len() {
+                    file_row += 1;
+                    line = 0;
                 }
-                self.delete_char()
-            },
-            Key::Up | Key::Down | Key::Left | Key::Right => self.move_cursor(c),
-            _ => {}
+            }
         }
-        self.quit_times = KILO_QUIT_TIMES;
-        return Ok(0)
+        let buffer = frame.row(rect.top + text_rows);
+        buffer.push_str(format!("{}", cursor::Goto(rect.left as u16 + 1, (rect.top + text_rows) as u16 + 1)).as_str());
+        self.status_bar(buffer, id, rect.width, view.cy);
     }
 
-    fn row_cx_to_rx(&mut self, row: usize, cx: usize) -> usize {
-        let mut rx = 0;
-        for j in self.rows[row].chars.chars().take(cx) {
-            if j == '\t' {
-                rx += (KILO_TAB_STOP - 1) - (rx % KILO_TAB_STOP);
+    // Next key from the terminal. When input ends or fails the editor quits,
+    // and Esc is returned to back out of any prompt in progress.
+    fn read_key(&mut self) -> Key {
+        let err = loop {
+            // A resize that came while drawing or handling the last key
+            // didn't interrupt a read, so it is looked for before blocking.
+            if terminal::resized() {
+                self.resize();
             }
-            rx += 1;
-        }
-        return rx;
+            // Changes not in a swap file yet are written once the user has
+            // stopped typing for a while.
+            if let Some(timeout) = self.swap_timeout() {
+                if !terminal::wait_for_input(timeout) {
+                    self.update_swap_files();
+                    continue
+                }
+            }
+            match self.stdin.next() {
+                Some(Ok(Event::Key(key))) => return key,
+                Some(Ok(_)) => {},
+                Some(Err(ref err)) if err.kind() == io::ErrorKind::Interrupted => {},
+                Some(Err(err)) => break err,
+                None => break io::Error::new(io::ErrorKind::UnexpectedEof, "end of input"),
+            }
+        };
+        self.failure = Some(Error::Io(err));
+        self.quit = true;
+        Key::Esc
     }
 
-    fn scroll_cursor(&mut self) {
-        self.rx = 0;
-        if self.cy < self.rows.len() {
-            let (cx, cy) = (self.cx, self.cy);
-            self.rx = self.row_cx_to_rx(cy, cx);
-        }
-
-        if self.cy < self.rowoff {
-            self.rowoff = self.cy;
+    fn process_keypress(&mut self) -> Result<i32, i32> {
+        if self.failure.is_some() {
+            return Err(1)
         }
-        if self.cy >= self.rowoff + self.screenrows as usize {
-            self.rowoff = self.cy - self.screenrows as usize + 1;
+        let c = self.read_key();
+        if self.failure.is_some() {
+            return Err(1)
         }
-        if self.rx < self.coloff {
-            self.coloff = self.rx;
+        let mode = self.mode;
+        let ret = mode.process_keypress(self, c);
+        match (self.mode, c) {
+            (Mode::Insert, Key::Char(_)) | (Mode::Insert, Key::Backspace) | (Mode::Insert, Key::Ctrl('h')) => {},
+            _ => self.buffer_mut().history.seal(),
         }
-        if self.rx >= self.coloff + self.screencols as usize {
-            self.coloff = self.rx - self.screencols as usize + 1;
+        if self.quit {
+            return Err(0)
         }
+        ret
     }
 
-    fn move_cursor(&mut self, key: Key) {
-        // let mut rowInput = None;
-        {
-            let row_length = if self.cy >= self.rows.len() {
-                0
-            } else {
-                self.rows[self.cy].chars.len()
-            };
-
-            match key {
-                Key::Down => self.cy += if self.cy < self.rows.len() { 1 } else { 0 },
-                Key::Up => self.cy -= if self.cy > 0 { 1 } else { 0 },
-                Key::Right => if row_length > 0 {
-                    if self.cx < row_length {
-                        self.cx += 1
-                    }
-                },
-                Key::Left => self.cx -= if self.cx > 0 { 1 } else { 0 },
-                _ => panic!("only call with cursor keys")
-            }
+    fn scroll_cursor(&mut self) {
+        let rect = self.window_rect(self.active);
+        let current = self.buffer_mut();
+        current.wrap_width = current.text_rect(rect).width;
+        current.rx = 0;
+        if current.cy < current.text.len() {
+            current.rx = current.row_cx_to_rx(current.cy, current.cx);
+        }
+        let view = current.view();
+        let (rowoff, coloff) = current.scroll_view(view, rect);
+        current.rowoff = rowoff;
+        current.coloff = coloff;
+    }
+
+    fn open_row(&mut self, below: bool) {
+        let current = self.buffer_mut();
+        let cy = current.cy;
+        if below && !current.text.is_empty() {
+            let len = current.text.line_bytes(cy);
+            current.insert_text((cy, len), "\n");
+        } else {
+            current.insert_text((cy, 0), "\n");
+            current.cy = cy;
+            current.cx = 0;
         }
+        self.mode = Mode::Insert;
+    }
 
-        if !(self.cy >= self.rows.len()) {
-            let rowlen = self.rows[self.cy].chars.len();
-            if self.cx > rowlen{
-                self.cx = rowlen;
-            }
-        };
+    fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        if self.mode != Mode::Visual {return None}
+        let cursor = (self.buffer().cy, self.buffer().cx);
+        if cursor < self.visual_start {
+            Some((cursor, self.visual_start))
+        } else {
+            Some((self.visual_start, cursor))
+        }
     }
 
+    fn row_selection(&self, at: usize) -> Option<(usize, usize)> {
+        let (start, end) = self.selection()?;
+        if at < start.0 || at > end.0 {return None}
+        let current = self.buffer();
+        let from = if at == start.0 {start.1} else {0};
+        let to = if at == end.0 {end.1 + 1} else {current.row_len(at)};
+        let to = to.min(current.row_len(at));
+        Some((current.row_cx_to_rx(at, from), current.row_cx_to_rx(at, to)))
+    }
 }
 
-fn init_editor() {
+fn init_editor() -> error::Result<()> {
     let args: Vec<String> = env::args().collect();
     let mut ret = Ok(1);
-    let mut editor = Editor::new();
-    editor.set_status_message("HELP: Ctrl-S = save | Ctrl-F = find | Ctrl-Q = quit".to_string());
-    if args.len() > 1 {
-        editor.read_file(args[1].clone());
+    let mut editor = Editor::new()?;
+    editor.set_status_message("HELP: i = insert | Esc = normal | Ctrl-S = save | Ctrl-F = find | Ctrl-R = replace | : = command | Ctrl-Q = quit".to_string());
+    // Opening the files sets messages of its own, the warnings are put in
+    // front of the last one so they are not lost.
+    let mut warnings: Vec<String> = editor.load_config().err().into_iter()
+        .chain(editor.load_syntaxes().err())
+        .collect();
+    for filename in args.iter().skip(1) {
+        editor.open_buffer(filename.clone());
+    }
+    if editor.buffers.len() > 1 {
+        editor.switch_buffer(0);
+    }
+    if !warnings.is_empty() {
+        if args.len() > 1 {
+            warnings.extend(editor.status_message.take().map(|(message, _)| message));
+        }
+        editor.set_status_message(warnings.join(" | "));
     }
 
-    while let Ok(_) = ret {
+    while ret.is_ok() {
         editor.draw();
         ret = editor.process_keypress();
+        editor.update_swap_files();
+    }
+    match editor.failure.take() {
+        Some(err) => {
+            editor.preserve_swap_files();
+            Err(err)
+        },
+        None => Ok(()),
     }
 }
 
-#[derive(Debug, Deserialize)]
-struct FileMatch {
-    filenames: Vec<String>,
-    keywords: Vec<String>,
-    important_keywords: String,
-    comments: Vec<String>,
-    numbers: String,
+fn main() {
+    if let Err(err) = init_editor() {
+        eprintln!("kilo-rs: {}", err);
+        process::exit(1);
+    }
 }
 
-fn main() -> io::Result<()> {
-    init_editor();
-    return Ok(());
+#[cfg(test)]
+mod tests {
+    use super::truncate;
+
+    #[test]
+    fn truncates_by_display_width() {
+        assert_eq!(truncate("abc", 5), ("abc", 3));
+        assert_eq!(truncate("abc", 2), ("ab", 2));
+        assert_eq!(truncate("日本語.txt", 5), ("日本", 4));
+        assert_eq!(truncate("e\u{301}e\u{301}", 1), ("e\u{301}", 1));
+        assert_eq!(truncate("日", 1), ("", 0));
+    }
 }
@@ -0,0 +1,85 @@
+use termion::event::Key;
+use Editor;
+
+pub struct Prompt {
+    pub message: String,
+    pub buffer: String,
+}
+
+pub type PromptCallback = fn(&mut Editor, &mut Prompt, Key);
+
+impl Editor {
+    // `history` names the list of previous answers browsed with Up/Down.
+    pub fn prompt(&mut self, message: String, history: Option<&'static str>, callback: Option<PromptCallback>) -> Option<String> {
+        self.read_prompt(message, history, callback, false)
+    }
+
+    // Like `prompt`, but Enter also accepts an empty answer.
+    pub fn prompt_allow_empty(&mut self, message: String, history: Option<&'static str>) -> Option<String> {
+        self.read_prompt(message, history, None, true)
+    }
+
+    fn read_prompt(&mut self, message: String, history: Option<&'static str>, callback: Option<PromptCallback>, allow_empty: bool) -> Option<String> {
+        let mut prompt = Prompt {
+            message,
+            buffer: String::new(),
+        };
+        let entries = history
+            .and_then(|name| self.prompt_histories.get(name).cloned())
+            .unwrap_or_default();
+        let mut index = entries.len();
+        let mut draft = String::new();
+        loop {
+            self.set_status_message(format!("{}{}", prompt.message, prompt.buffer));
+            self.draw();
+            let c = self.read_key();
+            match c {
+                Key::Delete | Key::Backspace => {prompt.buffer.pop();},
+                Key::Esc => {
+                    self.set_status_message("".to_string());
+                    if let Some(callback) = callback {
+                        callback(self, &mut prompt, c);
+                    }
+                    return None;
+                },
+                Key::Char('\n') if allow_empty || !prompt.buffer.is_empty() => {
+                    self.set_status_message("".to_string());
+                    if let Some(name) = history.filter(|_| !prompt.buffer.is_empty()) {
+                        self.remember(name, &prompt.buffer);
+                    }
+                    if let Some(callback) = callback {
+                        callback(self, &mut prompt, c);
+                    }
+                    return Some(prompt.buffer);
+                },
+                Key::Char('\n') => (),
+                Key::Up if index > 0 => {
+                    if index == entries.len() {
+                        draft = prompt.buffer.clone();
+                    }
+                    index -= 1;
+                    prompt.buffer = entries[index].clone();
+                },
+                Key::Down if index < entries.len() => {
+                    index += 1;
+                    prompt.buffer = entries.get(index).unwrap_or(&draft).clone();
+                },
+                Key::Char(c) => prompt.buffer.push(c),
+                _ => (),
+            }
+            if let Some(callback) = callback {
+                callback(self, &mut prompt, c);
+            }
+        }
+    }
+
+    pub fn remember(&mut self, history: &'static str, entry: &str) {
+        let entries = self.prompt_histories.entry(history).or_default();
+        entries.retain(|e| e != entry);
+        entries.push(entry.to_string());
+    }
+
+    pub fn last_entry(&self, history: &'static str) -> Option<String> {
+        self.prompt_histories.get(history).and_then(|entries| entries.last().cloned())
+    }
+}
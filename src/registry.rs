@@ -0,0 +1,172 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use toml::value::Value;
+use config;
+use error::{self, Error};
+use filetype::FileInfo;
+use syntax::{Syntax, SyntaxRe};
+use text::Text;
+use Editor;
+
+const DEFAULT_SYNTAX: &str = include_str!("../syntax.toml");
+
+struct Language {
+    syntax: Rc<Syntax>,
+    syntax_re: Rc<SyntaxRe>,
+}
+
+// Every known syntax, compiled once. Definitions are read from the built in
+// `syntax.toml`, then the `kilo-rs/syntax` directories under
+// `$XDG_DATA_DIRS` and `$XDG_CONFIG_HOME`, and a later definition of a
+// filetype replaces an earlier one. A file holds either a `[[syntax]]`
+// array or a single definition at the top level.
+pub struct SyntaxRegistry {
+    languages: Vec<Language>,
+    // Definitions that were left out, with the file and what is wrong.
+    pub warnings: Vec<String>,
+}
+
+impl SyntaxRegistry {
+    pub fn new() -> SyntaxRegistry {
+        SyntaxRegistry {
+            languages: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn load() -> SyntaxRegistry {
+        let mut registry = SyntaxRegistry::new();
+        registry.add_file("built-in syntax.toml", DEFAULT_SYNTAX);
+        for dir in syntax_dirs() {
+            registry.add_dir(&dir);
+        }
+        registry
+    }
+
+    fn add_dir(&mut self, dir: &Path) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return,
+            Err(err) => return self.warnings.push(format!("{}: {}", dir.display(), err)),
+        };
+        let mut paths: Vec<PathBuf> = entries.filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            match fs::read_to_string(&path) {
+                Ok(contents) => self.add_file(&path.display().to_string(), &contents),
+                Err(err) => self.warnings.push(format!("{}: {}", path.display(), err)),
+            }
+        }
+    }
+
+    fn add_file(&mut self, source: &str, contents: &str) {
+        let value = match contents.parse::<Value>() {
+            Ok(value) => value,
+            Err(err) => return self.warnings.push(format!("{}: {}", source, err)),
+        };
+        let entries = match value.get("syntax").cloned() {
+            Some(Value::Array(entries)) => entries,
+            Some(_) => return self.warnings.push(format!("{}: `syntax` must be an array of tables", source)),
+            None => vec![value],
+        };
+        for (i, entry) in entries.into_iter().enumerate() {
+            let name = match entry.get("filetype").and_then(Value::as_str) {
+                Some(filetype) => format!("syntax \"{}\"", filetype),
+                None => format!("syntax #{}", i + 1),
+            };
+            match compile(entry) {
+                Ok(language) => self.insert(language),
+                Err(err) => self.warnings.push(format!("{}: {}: {}", source, name, err)),
+            }
+        }
+    }
+
+    fn insert(&mut self, language: Language) {
+        let filetype = language.syntax.filetype.clone();
+        match self.languages.iter().position(|known| known.syntax.filetype == filetype) {
+            Some(i) => self.languages[i] = language,
+            None => self.languages.push(language),
+        }
+    }
+
+    // Highlighting for the file `filename` holding `text`, none when no
+    // syntax claims it. A modeline wins, then a listed file name or
+    // extension, a glob and last the `#!` line. Syntaxes added later are
+    // tried first.
+    pub fn detect(&self, filename: &str, text: &Text) -> (Rc<Syntax>, Rc<SyntaxRe>) {
+        let info = FileInfo::new(filename, text);
+        if let Some(found) = info.modeline.as_ref().and_then(|filetype| self.filetype(filetype)) {
+            return found
+        }
+        let tests: [&dyn Fn(&Syntax) -> bool; 3] = [
+            &|syntax| info.has_name(syntax),
+            &|syntax| info.matches_glob(syntax),
+            &|syntax| info.runs_with(syntax),
+        ];
+        tests.iter()
+            .filter_map(|test| self.languages.iter().rev().find(|language| test(&language.syntax)))
+            .next()
+            .map_or_else(plain, shared)
+    }
+
+    // The syntax of filetype `name`, which is plain text when empty.
+    pub fn filetype(&self, name: &str) -> Option<(Rc<Syntax>, Rc<SyntaxRe>)> {
+        if name.is_empty() {
+            return Some(plain())
+        }
+        self.languages.iter().find(|language| language.syntax.filetype == name).map(shared)
+    }
+
+    pub fn filetypes(&self) -> Vec<&str> {
+        let mut filetypes: Vec<&str> = self.languages.iter().map(|language| language.syntax.filetype.as_str()).collect();
+        filetypes.sort();
+        filetypes
+    }
+}
+
+fn plain() -> (Rc<Syntax>, Rc<SyntaxRe>) {
+    (Rc::new(Syntax::new()), Rc::new(SyntaxRe::plain()))
+}
+
+fn shared(language: &Language) -> (Rc<Syntax>, Rc<SyntaxRe>) {
+    (Rc::clone(&language.syntax), Rc::clone(&language.syntax_re))
+}
+
+fn compile(entry: Value) -> error::Result<Language> {
+    let syntax = entry.try_into::<Syntax>()?;
+    if syntax.filetype.is_empty() {
+        return Err(Error::Syntax("`filetype` must not be empty".to_string()))
+    }
+    let syntax_re = SyntaxRe::new(&syntax)?;
+    Ok(Language { syntax: Rc::new(syntax), syntax_re: Rc::new(syntax_re) })
+}
+
+// Directories searched for syntax files, the most important last.
+fn syntax_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = config::data_dirs().into_iter().rev().map(|dir| dir.join("syntax")).collect();
+    dirs.extend(config::config_dir().map(|dir| dir.join("syntax")));
+    dirs
+}
+
+impl Editor {
+    // Reads all syntax definitions again and rehighlights the open buffers.
+    pub fn load_syntaxes(&mut self) -> Result<(), String> {
+        self.syntaxes = SyntaxRegistry::load();
+        for buffer in &mut self.buffers {
+            if let Some(ref filename) = buffer.filename {
+                let (syntax, syntax_re) = self.syntaxes.detect(filename, &buffer.text);
+                buffer.set_syntax(syntax, syntax_re);
+            }
+        }
+        match self.syntaxes.warnings.len() {
+            0 => Ok(()),
+            1 => Err(format!("Syntax warning: {}", self.syntaxes.warnings[0])),
+            n => Err(format!("{} syntax warnings, see :syntax list: {}", n, self.syntaxes.warnings[0])),
+        }
+    }
+}
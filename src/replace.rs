@@ -0,0 +1,265 @@
+use regex::{Regex, RegexBuilder};
+use termion::event::Key;
+use search::SearchCase;
+use Editor;
+
+pub struct Substitute {
+    pub pattern: String,
+    pub replacement: String,
+    pub global: bool,
+    pub confirm: bool,
+    pub case: Option<SearchCase>,
+}
+
+impl Substitute {
+    // Parses what follows `s` in `:s/pattern/replacement/flags`. Any
+    // punctuation can be the delimiter and `\<delimiter>` escapes it.
+    // Flags are `g` (every match on a row), `c` (confirm), `i` and `I`.
+    pub fn parse(args: &str) -> Option<Substitute> {
+        let mut chars = args.chars();
+        let delimiter = chars.next()?;
+        if delimiter.is_alphanumeric() || delimiter.is_whitespace() || delimiter == '\\' {
+            return None
+        }
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for c in chars {
+            let last = parts.len() - 1;
+            if escaped {
+                if c != delimiter {
+                    parts[last].push('\\');
+                }
+                parts[last].push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == delimiter && parts.len() < 3 {
+                parts.push(String::new());
+            } else {
+                parts[last].push(c);
+            }
+        }
+        if escaped {
+            let last = parts.len() - 1;
+            parts[last].push('\\');
+        }
+        let mut substitute = Substitute {
+            pattern: parts[0].clone(),
+            replacement: expand_escapes(parts.get(1).map_or("", String::as_str)),
+            global: false,
+            confirm: false,
+            case: None,
+        };
+        for flag in parts.get(2).map_or("", String::as_str).trim().chars() {
+            match flag {
+                'g' => substitute.global = true,
+                'c' => substitute.confirm = true,
+                'i' => substitute.case = Some(SearchCase::Insensitive),
+                'I' => substitute.case = Some(SearchCase::Sensitive),
+                _ => return None,
+            }
+        }
+        Some(substitute)
+    }
+}
+
+// Turns `\1` style group references into the `${1}` form understood by
+// `Captures::expand`; `$1` and `${name}` can be used directly.
+fn expand_escapes(replacement: &str) -> String {
+    let mut expanded = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            expanded.push(c);
+            continue
+        }
+        match chars.next() {
+            Some(d) if d.is_ascii_digit() => expanded.push_str(&format!("${{{}}}", d)),
+            Some('t') => expanded.push('\t'),
+            Some(other) => expanded.push(other),
+            None => expanded.push('\\'),
+        }
+    }
+    expanded
+}
+
+impl Editor {
+    // Ctrl-R in insert mode: replace over the whole file, confirming each match.
+    pub fn replace(&mut self) {
+        let pattern = match self.prompt("Replace: ".to_string(), Some("search"), None) {
+            Some(pattern) => pattern,
+            None => return,
+        };
+        let message = format!("Replace {} with: ", pattern);
+        let replacement = match self.prompt_allow_empty(message, Some("replace")) {
+            Some(replacement) => replacement,
+            None => return,
+        };
+        let substitute = Substitute {
+            pattern,
+            replacement: expand_escapes(&replacement),
+            global: true,
+            confirm: true,
+            case: None,
+        };
+        let last = self.buffer().text.len().saturating_sub(1);
+        self.substitute(0, last, &substitute);
+    }
+
+    // Applies `substitute` to rows `first..=last` as a single undo step.
+    pub fn substitute(&mut self, first: usize, last: usize, substitute: &Substitute) {
+        if self.buffer().text.is_empty() {return}
+        let pattern = if substitute.pattern.is_empty() {
+            match self.last_entry("search") {
+                Some(pattern) => pattern,
+                None => return self.set_status_message("No previous search".to_string()),
+            }
+        } else {
+            self.remember("search", &substitute.pattern);
+            substitute.pattern.clone()
+        };
+        let case = substitute.case.unwrap_or(self.search_case);
+        let re = match RegexBuilder::new(&pattern).case_insensitive(case.ignores_case(&pattern)).build() {
+            Ok(re) => re,
+            Err(_) => return self.set_status_message(format!("Invalid pattern: {}", pattern)),
+        };
+
+        let saved = (self.buffer().cy, self.buffer().cx);
+        let last = last.min(self.buffer().text.len() - 1);
+        let mut all = !substitute.confirm;
+        let mut count = 0;
+        let mut found = false;
+        let mut last_row = None;
+        self.search = Some(re.clone());
+        self.buffer_mut().history.begin_group();
+        'rows: for y in first..last + 1 {
+            let line = self.buffer().text.line(y).to_string();
+            let matches = Editor::row_replacements(&re, &line, &substitute.replacement, substitute.global);
+            let mut delta = 0isize;
+            for (start, end, with) in matches {
+                found = true;
+                let start = (start as isize + delta) as usize;
+                let end = (end as isize + delta) as usize;
+                if !all {
+                    self.buffer_mut().set_cursor((y, start));
+                    match self.ask(format!("Replace with \"{}\"? (y/n/a/q)", with)) {
+                        Key::Char('y') => {},
+                        Key::Char('n') => continue,
+                        Key::Char('a') => all = true,
+                        _ => break 'rows,
+                    }
+                }
+                let buffer = self.buffer_mut();
+                buffer.delete_text((y, start), (y, end));
+                buffer.insert_text((y, start), &with);
+                delta += with.len() as isize - (end - start) as isize;
+                count += 1;
+                last_row = Some(y);
+            }
+        }
+        self.buffer_mut().history.end_group();
+        self.search = None;
+
+        match last_row {
+            Some(y) => {
+                self.buffer_mut().move_to_row(y);
+                let plural = if count == 1 {""} else {"s"};
+                self.set_status_message(format!("{} substitution{}", count, plural));
+            },
+            None => {
+                let buffer = self.buffer_mut();
+                buffer.cy = saved.0;
+                buffer.cx = saved.1;
+                if found {
+                    self.set_status_message("No substitutions".to_string());
+                } else {
+                    self.set_status_message(format!("Pattern not found: {}", pattern));
+                }
+            },
+        }
+    }
+
+    // Matches of `re` in `line` (only the first unless `global`), with the
+    // replacement text already expanded against the original row.
+    fn row_replacements(re: &Regex, line: &str, replacement: &str, global: bool) -> Vec<(usize, usize, String)> {
+        let limit = if global {usize::MAX} else {1};
+        re.captures_iter(line).take(limit).map(|caps| {
+            let m = caps.get(0).unwrap();
+            let mut with = String::new();
+            caps.expand(replacement, &mut with);
+            (m.start(), m.end(), with)
+        }).collect()
+    }
+
+    pub fn ask(&mut self, question: String) -> Key {
+        self.set_status_message(question);
+        self.draw();
+        let key = self.read_key();
+        self.set_status_message("".to_string());
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use search::SearchCase;
+    use Editor;
+    use super::{expand_escapes, Substitute};
+
+    fn parts(args: &str) -> (String, String) {
+        let substitute = Substitute::parse(args).unwrap();
+        (substitute.pattern, substitute.replacement)
+    }
+
+    #[test]
+    fn parses_pattern_and_replacement() {
+        assert_eq!(parts("/a/b/"), ("a".to_string(), "b".to_string()));
+        assert_eq!(parts("/a/b"), ("a".to_string(), "b".to_string()));
+        assert_eq!(parts("/a"), ("a".to_string(), "".to_string()));
+        assert_eq!(parts("//b/"), ("".to_string(), "b".to_string()));
+        assert_eq!(parts("#a/b#c#"), ("a/b".to_string(), "c".to_string()));
+    }
+
+    #[test]
+    fn escaped_delimiters_are_literal() {
+        assert_eq!(parts(r"/a\/b/c\/d/"), ("a/b".to_string(), "c/d".to_string()));
+        assert_eq!(parts(r"/a\d+/x/"), (r"a\d+".to_string(), "x".to_string()));
+        assert_eq!(parts(r"/a\"), (r"a\".to_string(), "".to_string()));
+    }
+
+    #[test]
+    fn parses_flags() {
+        let substitute = Substitute::parse("/a/b/gc").unwrap();
+        assert!(substitute.global && substitute.confirm);
+        assert_eq!(substitute.case, None);
+        assert_eq!(Substitute::parse("/a/b/i").unwrap().case, Some(SearchCase::Insensitive));
+        assert_eq!(Substitute::parse("/a/b/ I").unwrap().case, Some(SearchCase::Sensitive));
+        let substitute = Substitute::parse("/a/b/").unwrap();
+        assert!(!substitute.global && !substitute.confirm);
+    }
+
+    #[test]
+    fn rejects_bad_delimiters_and_flags() {
+        for args in &["", "xaxbx", " a b ", r"\a\b\", "/a/b/z", "/a/b/c/d"] {
+            assert!(Substitute::parse(args).is_none(), "{:?} should not parse", args);
+        }
+    }
+
+    #[test]
+    fn expands_group_references() {
+        assert_eq!(expand_escapes(r"\1-\t-\\-$2-${name}"), "${1}-\t-\\-$2-${name}");
+        assert_eq!(expand_escapes(r"a\"), r"a\");
+        assert_eq!(expand_escapes(r"\&"), "&");
+    }
+
+    #[test]
+    fn replaces_first_or_every_match() {
+        let re = Regex::new(r"(\w+)@").unwrap();
+        let replacement = expand_escapes(r"<\1>");
+        assert_eq!(Editor::row_replacements(&re, "a@ bc@", &replacement, true),
+                   vec![(0, 2, "<a>".to_string()), (3, 6, "<bc>".to_string())]);
+        assert_eq!(Editor::row_replacements(&re, "a@ bc@", &replacement, false),
+                   vec![(0, 2, "<a>".to_string())]);
+    }
+}
@@ -0,0 +1,178 @@
+use regex;
+use regex::{Regex, RegexBuilder};
+use termion::event::Key;
+use prompt::Prompt;
+use text::Position;
+use Editor;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SearchCase {
+    Smart,
+    Sensitive,
+    Insensitive,
+}
+
+impl SearchCase {
+    fn next(self) -> SearchCase {
+        match self {
+            SearchCase::Smart => SearchCase::Sensitive,
+            SearchCase::Sensitive => SearchCase::Insensitive,
+            SearchCase::Insensitive => SearchCase::Smart,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SearchCase::Smart => "smart case",
+            SearchCase::Sensitive => "match case",
+            SearchCase::Insensitive => "ignore case",
+        }
+    }
+
+    // Name used by `:set searchcase=`.
+    pub fn name(self) -> &'static str {
+        match self {
+            SearchCase::Smart => "smart",
+            SearchCase::Sensitive => "match",
+            SearchCase::Insensitive => "ignore",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SearchCase> {
+        [SearchCase::Smart, SearchCase::Sensitive, SearchCase::Insensitive].iter()
+            .cloned()
+            .find(|case| case.name() == name)
+    }
+
+    pub fn ignores_case(self, query: &str) -> bool {
+        match self {
+            SearchCase::Smart => !query.chars().any(char::is_uppercase),
+            SearchCase::Sensitive => false,
+            SearchCase::Insensitive => true,
+        }
+    }
+}
+
+// Queries that are not valid regular expressions yet (`foo(` while typing)
+// are searched for literally.
+pub fn search_regex(query: &str, case: SearchCase) -> Option<Regex> {
+    if query.is_empty() {return None}
+    let ignore_case = case.ignores_case(query);
+    RegexBuilder::new(query).case_insensitive(ignore_case).build()
+        .or_else(|_| RegexBuilder::new(&regex::escape(query)).case_insensitive(ignore_case).build())
+        .ok()
+}
+
+impl Editor {
+    pub fn find(&mut self) {
+        let saved_cx = self.buffer().cx;
+        let saved_cy = self.buffer().cy;
+        let saved_coloff = self.buffer().coloff;
+        let saved_rowoff = self.buffer().rowoff;
+        self.search_origin = self.buffer().cursor_position();
+
+        let message = self.search_message();
+        let query = self.prompt(message, Some("search"), Some(Editor::find_callback));
+        self.search = None;
+
+        if query.is_none() {
+            let buffer = self.buffer_mut();
+            buffer.cx = saved_cx;
+            buffer.cy = saved_cy;
+            buffer.coloff = saved_coloff;
+            buffer.rowoff = saved_rowoff;
+        }
+    }
+
+    pub fn find_again(&mut self, forward: bool) {
+        let query = match self.last_entry("search") {
+            Some(query) => query,
+            None => return self.set_status_message("No previous search".to_string()),
+        };
+        let re = match search_regex(&query, self.search_case) {
+            Some(re) => re,
+            None => return,
+        };
+        let from = self.buffer().cursor_position();
+        let found = if forward {
+            self.find_next(&re, from, false)
+        } else {
+            self.find_previous(&re, from)
+        };
+        match found {
+            Some(position) => self.buffer_mut().set_cursor(position),
+            None => self.set_status_message(format!("Pattern not found: {}", query)),
+        }
+    }
+
+    fn search_message(&self) -> String {
+        format!("Search [{}] (\u{2190}/\u{2192} match, \u{2191}/\u{2193} history, Ctrl-T case): ", self.search_case.label())
+    }
+
+    fn find_callback(editor: &mut Editor, prompt: &mut Prompt, key: Key) {
+        if key == Key::Ctrl('t') {
+            editor.search_case = editor.search_case.next();
+            prompt.message = editor.search_message();
+        }
+        editor.search = search_regex(&prompt.buffer, editor.search_case);
+        let re = match editor.search.clone() {
+            Some(re) => re,
+            None => return,
+        };
+        let found = match key {
+            Key::Char('\n') | Key::Esc => return,
+            Key::Right | Key::Ctrl('n') => {
+                let from = editor.buffer().cursor_position();
+                editor.find_next(&re, from, false)
+            },
+            Key::Left | Key::Ctrl('p') => {
+                let from = editor.buffer().cursor_position();
+                editor.find_previous(&re, from)
+            },
+            _ => {
+                let origin = editor.search_origin;
+                Some(editor.find_next(&re, origin, true).unwrap_or(origin))
+            },
+        };
+        if let Some(position) = found {
+            editor.buffer_mut().set_cursor(position);
+        }
+    }
+
+    fn find_next(&self, re: &Regex, from: Position, inclusive: bool) -> Option<Position> {
+        let text = &self.buffer().text;
+        let rows = text.len();
+        if rows == 0 {return None}
+        let (from, inclusive) = if from.0 >= rows {((0, 0), true)} else {(from, inclusive)};
+        for i in 0..rows + 1 {
+            let y = (from.0 + i) % rows;
+            let line = text.line(y).to_string();
+            let found = re.find_iter(&line)
+                .filter(|m| m.end() > m.start())
+                .find(|m| i > 0 || m.start() > from.1 || (inclusive && m.start() == from.1));
+            if let Some(m) = found {
+                return Some((y, m.start()))
+            }
+        }
+        None
+    }
+
+    fn find_previous(&self, re: &Regex, from: Position) -> Option<Position> {
+        let text = &self.buffer().text;
+        let rows = text.len();
+        if rows == 0 {return None}
+        let from = if from.0 >= rows {(rows - 1, text.line_bytes(rows - 1) + 1)} else {from};
+        for i in 0..rows + 1 {
+            let y = (from.0 + 2 * rows - i) % rows;
+            let line = text.line(y).to_string();
+            let found = re.find_iter(&line)
+                .filter(|m| m.end() > m.start())
+                .filter(|m| i > 0 || m.start() < from.1)
+                .last();
+            if let Some(m) = found {
+                return Some((y, m.start()))
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,260 @@
+use std::fs::{self, File};
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+use termion::event::Key;
+use buffer::Buffer;
+use file;
+use text::{Format, Position};
+use Editor;
+
+// A swap file is written on the first change and then once this much time
+// or this many changes have gone by, or the user stops typing for as long.
+const SWAP_INTERVAL: Duration = Duration::from_secs(4);
+const SWAP_CHANGES: usize = 200;
+const SWAP_MAGIC: &str = "kilo-rs swap";
+
+// The swap file of `filename` is a hidden file next to it.
+pub fn swap_path(filename: &str) -> PathBuf {
+    let path = Path::new(filename);
+    let name = path.file_name().map_or("".into(), |name| name.to_string_lossy());
+    path.with_file_name(format!(".{}.swp", name))
+}
+
+// Swap file state of a buffer. The file only exists while the buffer has
+// unsaved changes. No swap file is written while another running editor
+// owns the one for this file.
+pub struct Swap {
+    path: Option<PathBuf>,
+    version: usize,
+    written: Option<Instant>,
+    disabled: bool,
+    // The last write failed, which has been reported.
+    failed: bool,
+}
+
+impl Swap {
+    pub fn new() -> Swap {
+        Swap {
+            path: None,
+            version: 0,
+            written: None,
+            disabled: false,
+            failed: false,
+        }
+    }
+}
+
+// A swap file holds a header line with the pid of the editor that wrote it
+// and the cursor, followed by the rows of the buffer in UTF-8.
+struct Recovered {
+    pid: u32,
+    cursor: Position,
+    content: String,
+}
+
+fn read_swap(path: &Path) -> io::Result<Recovered> {
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+    let damaged = || io::Error::new(io::ErrorKind::InvalidData, "damaged swap file");
+    let newline = content.find('\n').ok_or_else(damaged)?;
+    let header: Vec<&str> = content[..newline].split(' ').collect();
+    if header.len() != 5 || header[..2].join(" ") != SWAP_MAGIC {
+        return Err(damaged())
+    }
+    let number = |i: usize| header[i].parse::<usize>().map_err(|_| damaged());
+    Ok(Recovered {
+        pid: number(2)? as u32,
+        cursor: (number(3)?, number(4)?),
+        content: content[newline + 1..].to_string(),
+    })
+}
+
+// Rows are written to the swap file as they are, CRs included.
+fn swap_format() -> Format {
+    Format { keep_cr: true, ..Format::new() }
+}
+
+// Whether another editor that is still running owns the swap file. Only
+// known where /proc exists.
+fn in_use(pid: u32) -> bool {
+    pid != process::id() && Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+impl Buffer {
+    // Called after every key and when waiting for one times out. Removes
+    // the swap file once the buffer is clean again (saved, or undone back to
+    // the saved state). Only the first of failing writes in a row is an
+    // error, so it is reported once.
+    pub fn update_swap(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            self.remove_swap();
+            return Ok(())
+        }
+        if !self.swap_pending() {
+            return Ok(())
+        }
+        let due = match self.swap.written {
+            Some(written) => written.elapsed() >= SWAP_INTERVAL || self.version - self.swap.version >= SWAP_CHANGES,
+            None => true,
+        };
+        if !due {
+            return Ok(())
+        }
+        let failed = self.swap.failed;
+        let result = self.write_swap();
+        self.swap.failed = result.is_err();
+        if failed {Ok(())} else {result}
+    }
+
+    fn swap_pending(&self) -> bool {
+        self.dirty && self.version != self.swap.version && !self.swap.disabled && self.filename.is_some()
+    }
+
+    // How long until changes not yet in the swap file are due to be written.
+    fn swap_due_in(&self) -> Option<Duration> {
+        if !self.swap_pending() {return None}
+        Some(self.swap.written.map_or(Duration::ZERO, |written| SWAP_INTERVAL.saturating_sub(written.elapsed())))
+    }
+
+    fn write_swap(&mut self) -> io::Result<()> {
+        let path = match self.filename {
+            Some(ref filename) => swap_path(filename),
+            None => return Ok(()),
+        };
+        // Saving under another name moves the swap file along.
+        if self.swap.path.as_ref().is_some_and(|old| *old != path) {
+            self.remove_swap();
+        }
+        self.swap.version = self.version;
+        self.swap.written = Some(Instant::now());
+        let header = format!("{} {} {} {}\n", SWAP_MAGIC, process::id(), self.cy, self.cx);
+        let text = &self.text;
+        // Nothing is synced, a swap file is rewritten too often for that
+        // and `preserve_swap` covers the ways the editor can die.
+        file::write_atomic(&path.to_string_lossy(), false, |file| {
+            let mut writer = BufWriter::new(file);
+            writer.write_all(header.as_bytes())?;
+            let written = text.write_to(&mut writer, swap_format())?;
+            writer.flush()?;
+            Ok(header.len() + written)
+        })?;
+        self.swap.path = Some(path);
+        Ok(())
+    }
+
+    // Brings the swap file up to date and leaves it behind for recovery.
+    pub fn preserve_swap(&mut self) {
+        if self.dirty && self.version != self.swap.version && !self.swap.disabled {
+            let _ = self.write_swap();
+        }
+        self.swap.path = None;
+    }
+
+    pub fn remove_swap(&mut self) {
+        if let Some(path) = self.swap.path.take() {
+            let _ = fs::remove_file(path);
+        }
+        self.swap.written = None;
+    }
+}
+
+impl Drop for Buffer {
+    // Closing a buffer or quitting discards its swap file, a panic keeps
+    // it so the changes can be recovered.
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.preserve_swap();
+        } else {
+            self.remove_swap();
+        }
+    }
+}
+
+impl Editor {
+    // How long reading a key may wait before a swap file is due.
+    pub fn swap_timeout(&self) -> Option<Duration> {
+        self.buffers.iter().filter_map(Buffer::swap_due_in).min()
+    }
+
+    pub fn update_swap_files(&mut self) {
+        let mut error = None;
+        for buffer in &mut self.buffers {
+            if let Err(err) = buffer.update_swap() {
+                error = Some(err);
+            }
+        }
+        if let Some(err) = error {
+            self.set_status_message(format!("Can't write swap file: {}", err));
+        }
+    }
+
+    // For when the editor has to give up without a panic.
+    pub fn preserve_swap_files(&mut self) {
+        for buffer in &mut self.buffers {
+            buffer.preserve_swap();
+        }
+    }
+
+    // Offers to recover the current buffer from a swap file left behind by
+    // an editor that did not exit cleanly.
+    pub fn check_swap(&mut self) {
+        let path = match self.buffer().filename {
+            Some(ref filename) => swap_path(filename),
+            None => return,
+        };
+        let recovered = match read_swap(&path) {
+            Ok(recovered) => recovered,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return,
+            Err(err) => return self.set_status_message(format!("Can't read swap file \"{}\": {}", path.display(), err)),
+        };
+        let running = in_use(recovered.pid);
+        let mut current = Vec::new();
+        if !running && self.buffer().text.write_to(&mut current, swap_format()).is_ok() && current == recovered.content.as_bytes() {
+            let _ = fs::remove_file(&path);
+            return
+        }
+        let owner = if running {format!(", in use by process {}", recovered.pid)} else {String::new()};
+        let question = format!("Swap file \"{}\" found{}: (r)ecover, (d)elete it, (e)dit anyway", path.display(), owner);
+        match self.ask(question) {
+            Key::Char('r') => self.recover(recovered, path, running),
+            Key::Char('d') => {
+                if let Err(err) = fs::remove_file(&path) {
+                    self.set_status_message(format!("Can't delete swap file: {}", err));
+                }
+            },
+            _ if running => {
+                self.buffer_mut().swap.disabled = true;
+                self.set_status_message("Swap file in use, no swap file is written for this buffer".to_string());
+            },
+            _ => {},
+        }
+    }
+
+    // Replaces the text with the swap file's as one undo step, so undo
+    // goes back to what is on disk. The swap file is only taken over when
+    // the editor that wrote it is gone.
+    fn recover(&mut self, recovered: Recovered, path: PathBuf, running: bool) {
+        let buffer = self.buffer_mut();
+        let content = recovered.content.strip_suffix('\n').unwrap_or(&recovered.content);
+        buffer.history.begin_group();
+        let rows = buffer.text.len();
+        buffer.delete_text((0, 0), (rows, 0));
+        if !content.is_empty() {
+            buffer.insert_text((0, 0), content);
+        }
+        buffer.history.end_group();
+        buffer.cy = recovered.cursor.0;
+        buffer.cx = recovered.cursor.1;
+        buffer.clamp_cursor();
+        if running {
+            buffer.swap.disabled = true;
+        } else {
+            buffer.swap.path = Some(path);
+        }
+        self.set_status_message("Recovered from swap file, save to keep the changes".to_string());
+    }
+}
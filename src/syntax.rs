@@ -0,0 +1,273 @@
+use regex;
+use regex::Regex;
+use std::slice;
+use error::{self, Error};
+use theme::Group;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Syntax {
+    pub filetype: String,
+    // Extensions and whole file names (`rs`, `Makefile`).
+    pub filenames: Vec<String>,
+    #[serde(default)]
+    pub globs: Vec<String>,
+    // Programs named on a `#!` line.
+    #[serde(default)]
+    pub interpreters: Vec<String>,
+    #[serde(default)]
+    keywords: Vec<String>,
+    #[serde(default)]
+    important_keywords: String,
+    #[serde(default)]
+    types: Vec<String>,
+    #[serde(default)]
+    numbers: String,
+    #[serde(default)]
+    comments: String,
+    #[serde(default)]
+    multiline_comments: Vec<(String, String)>,
+    #[serde(default)]
+    strings: Vec<String>,
+}
+
+impl Syntax {
+    pub fn new() -> Syntax {
+        Syntax {
+            filetype: "".to_string(),
+            filenames: vec!(),
+            globs: vec!(),
+            interpreters: vec!(),
+            keywords: vec!(),
+            important_keywords: "".to_string(),
+            types: vec!(),
+            numbers: "".to_string(),
+            comments: "".to_string(),
+            multiline_comments: vec!(),
+            strings: vec!(),
+        }
+    }
+}
+
+pub struct SyntaxRe {
+    numbers: Option<Regex>,
+    keywords: Option<Regex>,
+    important_keywords: Option<Regex>,
+    types: Option<Regex>,
+    comments: Option<Regex>,
+    multiline_comments: Vec<(String, String)>,
+    strings: Vec<String>,
+}
+
+impl SyntaxRe {
+    // Fails naming the field with an invalid pattern.
+    pub fn new(syntax: &Syntax) -> error::Result<SyntaxRe> {
+        let numbers = if syntax.numbers.is_empty() {
+            None
+        } else {
+            Some(Regex::new(&syntax.numbers).map_err(invalid("numbers"))?)
+        };
+        let keywords = words_regex(&syntax.keywords).map_err(invalid("keywords"))?;
+        let important_keywords = words_regex(slice::from_ref(&syntax.important_keywords)).map_err(invalid("important_keywords"))?;
+        let types = words_regex(&syntax.types).map_err(invalid("types"))?;
+        let comments = if syntax.comments.is_empty() {
+            None
+        } else {
+            Some(Regex::new(&syntax.comments).map_err(invalid("comments"))?)
+        };
+        Ok(SyntaxRe {
+            numbers,
+            keywords,
+            important_keywords,
+            types,
+            comments,
+            multiline_comments: syntax.multiline_comments.clone(),
+            strings: syntax.strings.clone(),
+        })
+    }
+
+    // No highlighting at all.
+    pub fn plain() -> SyntaxRe {
+        SyntaxRe {
+            numbers: None,
+            keywords: None,
+            important_keywords: None,
+            types: None,
+            comments: None,
+            multiline_comments: Vec::new(),
+            strings: Vec::new(),
+        }
+    }
+
+    pub fn highlight(&self, line: &str, start: HlState, highlight: &mut [Highlight]) -> HlState {
+        let classes = [
+            (&self.numbers, Highlight::Number),
+            (&self.types, Highlight::Type),
+            (&self.keywords, Highlight::Keyword),
+            (&self.important_keywords, Highlight::ImportantKeyword),
+        ];
+        for &(re, hl) in classes.iter() {
+            if let Some(ref re) = *re {
+                // The first group is highlighted, or the whole match when
+                // a `numbers` pattern has none.
+                for capture in re.captures_iter(line) {
+                    if let Some(mat) = capture.get(1).or_else(|| capture.get(0)) {
+                        mark(highlight, mat.start(), mat.end(), hl);
+                    }
+                }
+            }
+        }
+        self.scan(line, start, highlight)
+    }
+
+    pub fn has_multiline(&self) -> bool {
+        !self.multiline_comments.is_empty()
+    }
+
+    // Marks strings and comments in `line` and returns the state the next
+    // row starts in. Strings never span rows, block comments do.
+    pub fn scan(&self, line: &str, start: HlState, highlight: &mut [Highlight]) -> HlState {
+        let mut state = start;
+        let mut string: Option<&str> = None;
+        let mut next_comment = self.comments.as_ref().and_then(|re| re.find_at(line, 0));
+        let mut i = 0;
+        'scan: while i < line.len() {
+            let rest = &line[i..];
+            if let HlState::Comment(open) = state {
+                let end = &self.multiline_comments[open].1;
+                if rest.starts_with(end.as_str()) {
+                    mark(highlight, i, i + end.len(), Highlight::Comment);
+                    i += end.len();
+                    state = HlState::Normal;
+                    continue
+                }
+            } else if let Some(quote) = string {
+                let skip = if rest.starts_with('\\') && rest.len() > 1 {
+                    1 + rest[1..].chars().next().map_or(0, char::len_utf8)
+                } else if rest.starts_with(quote) {
+                    string = None;
+                    quote.len()
+                } else {
+                    0
+                };
+                if skip > 0 {
+                    mark(highlight, i, i + skip, Highlight::String);
+                    i += skip;
+                    continue
+                }
+            } else {
+                if next_comment.is_some_and(|m| m.start() < i) {
+                    next_comment = self.comments.as_ref().and_then(|re| re.find_at(line, i));
+                }
+                if let Some(comment) = next_comment.filter(|m| m.start() == i && m.end() > i) {
+                    mark(highlight, i, comment.end(), Highlight::Comment);
+                    i = comment.end();
+                    continue
+                }
+                for (idx, (open, _)) in self.multiline_comments.iter().enumerate() {
+                    if !open.is_empty() && rest.starts_with(open.as_str()) {
+                        mark(highlight, i, i + open.len(), Highlight::Comment);
+                        i += open.len();
+                        state = HlState::Comment(idx);
+                        continue 'scan
+                    }
+                }
+                for quote in &self.strings {
+                    if !quote.is_empty() && rest.starts_with(quote.as_str()) {
+                        mark(highlight, i, i + quote.len(), Highlight::String);
+                        i += quote.len();
+                        string = Some(quote);
+                        continue 'scan
+                    }
+                }
+            }
+            let len = rest.chars().next().map_or(1, char::len_utf8);
+            match state {
+                HlState::Comment(_) => mark(highlight, i, i + len, Highlight::Comment),
+                HlState::Normal if string.is_some() => mark(highlight, i, i + len, Highlight::String),
+                HlState::Normal => {},
+            }
+            i += len;
+        }
+        state
+    }
+
+    pub fn end_state(&self, line: &str, start: HlState) -> HlState {
+        let mut scratch = vec![Highlight::Normal; line.len()];
+        self.scan(line, start, &mut scratch)
+    }
+}
+
+// Regex errors span several lines, which the message bar can't show.
+fn invalid(field: &'static str) -> impl Fn(regex::Error) -> Error {
+    move |err| {
+        let message: Vec<String> = err.to_string().split_whitespace().map(str::to_string).collect();
+        Error::Syntax(format!("invalid `{}`: {}", field, message.join(" ")))
+    }
+}
+
+// Alternatives are joined into one `\b(...)\b` group, empty entries are skipped.
+fn words_regex(words: &[String]) -> Result<Option<Regex>, regex::Error> {
+    let words: Vec<&str> = words.iter().map(String::as_str).filter(|w| !w.is_empty()).collect();
+    if words.is_empty() {
+        Ok(None)
+    } else {
+        Regex::new(&format!(r"\b({})\b", words.join("|"))).map(Some)
+    }
+}
+
+fn mark(highlight: &mut [Highlight], start: usize, end: usize, hl: Highlight) {
+    let end = end.min(highlight.len());
+    for h in &mut highlight[start.min(end)..end] {
+        *h = hl;
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HlState {
+    Normal,
+    Comment(usize),
+}
+
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+pub enum Highlight {
+    Normal,
+    Number,
+    Type,
+    Keyword,
+    ImportantKeyword,
+    String,
+    Comment,
+    Match,
+    Selection,
+}
+
+impl Highlight {
+    pub fn group(self) -> Group {
+        match self {
+            Highlight::Normal => Group::Normal,
+            Highlight::Number => Group::Number,
+            Highlight::Type => Group::Type,
+            Highlight::Keyword => Group::Keyword,
+            Highlight::ImportantKeyword => Group::ImportantKeyword,
+            Highlight::String => Group::String,
+            Highlight::Comment => Group::Comment,
+            Highlight::Match => Group::SearchMatch,
+            Highlight::Selection => Group::Selection,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use toml;
+    use super::{Syntax, SyntaxRe};
+
+    #[test]
+    fn only_filetype_and_filenames_are_required() {
+        let syntax: Syntax = toml::from_str("filetype = \"ini\"\nfilenames = [\"ini\"]").unwrap();
+        assert_eq!(syntax.filetype, "ini");
+        assert!(SyntaxRe::new(&syntax).is_ok());
+        assert!(toml::from_str::<Syntax>("filenames = [\"ini\"]").is_err());
+    }
+}
@@ -0,0 +1,134 @@
+use libc;
+use std::io;
+use std::io::{stdout, Read, Stdout, Write};
+use std::mem;
+use std::panic;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, Once};
+use std::time::Duration;
+use termion::cursor;
+use termion::screen::{ToAlternateScreen, ToMainScreen};
+
+// Terminal attributes from before raw mode, taken back by whichever of the
+// panic hook and `Terminal::drop` runs first.
+static ORIGINAL: Mutex<Option<libc::termios>> = Mutex::new(None);
+static PANIC_HOOK: Once = Once::new();
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+// Raw mode on the alternate screen for as long as it lives. A panic puts the
+// terminal back before the panic message is printed, so that it can be read.
+pub struct Terminal {
+    stdout: Stdout,
+}
+
+impl Terminal {
+    pub fn new() -> io::Result<Terminal> {
+        let mut ios: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDOUT_FILENO, &mut ios) } != 0 {
+            return Err(io::Error::last_os_error())
+        }
+        let original = ios;
+        unsafe { libc::cfmakeraw(&mut ios) };
+        set_attributes(&ios)?;
+        if let Ok(mut saved) = ORIGINAL.lock() {
+            *saved = Some(original);
+        }
+        // From here on dropping the guard on an error restores the terminal.
+        let mut terminal = Terminal { stdout: stdout() };
+        watch_resize()?;
+        PANIC_HOOK.call_once(|| {
+            let default = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                restore();
+                default(info);
+            }));
+        });
+        write!(terminal, "{}", ToAlternateScreen)?;
+        terminal.flush()?;
+        Ok(terminal)
+    }
+}
+
+impl Write for Terminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+// Standard input without a buffer of its own, so that `wait_for_input`
+// never misses keys that were read but not handed out yet.
+pub struct Input;
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if read < 0 {
+            return Err(io::Error::last_os_error())
+        }
+        Ok(read as usize)
+    }
+}
+
+// Waits up to `timeout` for input, or for the end of it. A resize ends
+// the wait early without any.
+pub fn wait_for_input(timeout: Duration) -> bool {
+    let mut fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+    let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    unsafe { libc::poll(&mut fd, 1, timeout) > 0 }
+}
+
+// Whether the terminal was resized since the last call.
+pub fn resized() -> bool {
+    RESIZED.swap(false, Ordering::SeqCst)
+}
+
+extern "C" fn on_resize(_: libc::c_int) {
+    RESIZED.store(true, Ordering::SeqCst);
+}
+
+// Without SA_RESTART a resize interrupts the blocking read of the next key,
+// so the screen can be redrawn before the user types anything.
+fn watch_resize() -> io::Result<()> {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = on_resize as *const () as libc::sighandler_t;
+        action.sa_flags = 0;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGWINCH, &action, ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error())
+        }
+    }
+    Ok(())
+}
+
+fn set_attributes(ios: &libc::termios) -> io::Result<()> {
+    if unsafe { libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSADRAIN, ios) } != 0 {
+        return Err(io::Error::last_os_error())
+    }
+    Ok(())
+}
+
+// Leaves the alternate screen and restores cooked mode, once.
+fn restore() {
+    let original = match ORIGINAL.lock() {
+        Ok(mut saved) => saved.take(),
+        Err(_) => return,
+    };
+    if let Some(ios) = original {
+        let mut stdout = stdout();
+        let _ = write!(stdout, "{}{}", cursor::Show, ToMainScreen);
+        let _ = stdout.flush();
+        let _ = set_attributes(&ios);
+    }
+}
@@ -0,0 +1,313 @@
+use ropey::{Rope, RopeBuilder, RopeSlice};
+use std::io;
+use std::io::Write;
+use unicode_segmentation::UnicodeSegmentation;
+use encoding::{Decoded, Encoding};
+
+// A row and a byte offset inside it. Cursor columns count grapheme clusters
+// instead and are converted with `byte_offset` and `column`.
+pub type Position = (usize, usize);
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+
+    // Name used by `:set fileformat=`.
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "unix",
+            LineEnding::Crlf => "dos",
+            LineEnding::Cr => "mac",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LineEnding> {
+        [LineEnding::Lf, LineEnding::Crlf, LineEnding::Cr].iter()
+            .cloned()
+            .find(|ending| ending.name() == name || ending.label().eq_ignore_ascii_case(name))
+    }
+
+    // The line ending `content` uses throughout, LF when there is none and
+    // nothing when it mixes them.
+    fn detect(content: &str) -> Option<LineEnding> {
+        let (mut lf, mut crlf, mut cr) = (0, 0, 0);
+        let mut bytes = content.bytes().peekable();
+        while let Some(byte) = bytes.next() {
+            match byte {
+                b'\r' if bytes.peek() == Some(&b'\n') => {
+                    bytes.next();
+                    crlf += 1;
+                },
+                b'\r' => cr += 1,
+                b'\n' => lf += 1,
+                _ => {},
+            }
+        }
+        match (lf, crlf, cr) {
+            (_, 0, 0) => Some(LineEnding::Lf),
+            (0, _, 0) => Some(LineEnding::Crlf),
+            (0, 0, _) => Some(LineEnding::Cr),
+            _ => None,
+        }
+    }
+}
+
+// How rows are laid out in the file, kept so that saving writes the same
+// bytes back.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Format {
+    pub encoding: Encoding,
+    pub line_ending: LineEnding,
+    pub final_newline: bool,
+    pub bom: bool,
+    // Rows of files that mix line endings, and of binary files, keep the CR
+    // of a CRLF and are written back as they are. Once a line ending is set
+    // for the whole file those CRs are dropped.
+    pub keep_cr: bool,
+}
+
+impl Format {
+    pub fn new() -> Format {
+        Format {
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            final_newline: true,
+            bom: false,
+            keep_cr: false,
+        }
+    }
+
+    // Shown in the status bar, e.g. "CRLF noeol BOM" or "utf-16le LF".
+    // UTF-16 files always have a BOM, so it is only mentioned for UTF-8.
+    pub fn label(self) -> String {
+        let mut label = String::new();
+        if self.encoding != Encoding::Utf8 {
+            label.push_str(self.encoding.name());
+            label.push(' ');
+        }
+        label.push_str(self.line_ending.label());
+        if !self.final_newline {
+            label.push_str(" noeol");
+        }
+        if self.bom && self.encoding == Encoding::Utf8 {
+            label.push_str(" BOM");
+        }
+        label
+    }
+}
+
+// Rows are stored newline-terminated, so an empty rope has no rows at all
+// while "\n" is a single empty row.
+pub struct Text {
+    rope: Rope,
+}
+
+impl Text {
+    pub fn new() -> Text {
+        Text {
+            rope: Rope::new(),
+        }
+    }
+
+    // Rows end with "\n" whatever the file uses. Files mixing line endings,
+    // and binary files, are split at "\n" only and every CR stays in its
+    // row, so they are written back unchanged.
+    pub fn from_decoded(decoded: Decoded) -> (Text, Format) {
+        let mut format = Format::new();
+        format.encoding = decoded.encoding;
+        format.bom = decoded.bom;
+        let content = decoded.text;
+        let detected = if decoded.binary {None} else {LineEnding::detect(&content)};
+        format.line_ending = detected.unwrap_or(LineEnding::Lf);
+        format.keep_cr = detected.is_none();
+        let mut content = match format.line_ending {
+            LineEnding::Lf => content,
+            LineEnding::Crlf => content.replace("\r\n", "\n"),
+            LineEnding::Cr => content.replace('\r', "\n"),
+        };
+        if !content.is_empty() && !content.ends_with('\n') {
+            format.final_newline = false;
+            content.push('\n');
+        }
+        let mut builder = RopeBuilder::new();
+        builder.append(&content);
+        (Text { rope: builder.finish() }, format)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rope.len_lines() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn line(&self, y: usize) -> RopeSlice<'_> {
+        let line = self.rope.line(y);
+        let len = line.len_chars();
+        if len > 0 && line.char(len - 1) == '\n' {
+            line.slice(..len - 1)
+        } else {
+            line
+        }
+    }
+
+    pub fn line_bytes(&self, y: usize) -> usize {
+        if y < self.len() {
+            self.line(y).len_bytes()
+        } else {
+            0
+        }
+    }
+
+    pub fn line_len(&self, y: usize) -> usize {
+        if y < self.len() {
+            self.line(y).to_string().graphemes(true).count()
+        } else {
+            0
+        }
+    }
+
+    pub fn byte_offset(&self, y: usize, column: usize) -> usize {
+        if y >= self.len() {return 0}
+        let line = self.line(y).to_string();
+        line.grapheme_indices(true).nth(column).map_or(line.len(), |(idx, _)| idx)
+    }
+
+    pub fn column(&self, y: usize, byte: usize) -> usize {
+        if y >= self.len() {return 0}
+        self.line(y).to_string().grapheme_indices(true).take_while(|&(idx, _)| idx < byte).count()
+    }
+
+    pub fn grapheme(&self, y: usize, column: usize) -> Option<String> {
+        if y >= self.len() {return None}
+        self.line(y).to_string().graphemes(true).nth(column).map(str::to_string)
+    }
+
+    fn char_index(&self, (y, x): Position) -> usize {
+        self.rope.line_to_char(y) + self.line(y).byte_to_char(x)
+    }
+
+    pub fn insert(&mut self, at: Position, text: &str) {
+        if at.0 >= self.len() {
+            let end = self.rope.len_chars();
+            self.rope.insert(end, "\n");
+        }
+        let at = (at.0.min(self.len() - 1), at.1);
+        let idx = self.char_index(at);
+        self.rope.insert(idx, text);
+    }
+
+    pub fn slice(&self, start: Position, end: Position) -> String {
+        let (start, end) = (self.char_index(start), self.char_index(end));
+        self.rope.slice(start..end).to_string()
+    }
+
+    pub fn remove(&mut self, start: Position, end: Position) {
+        let (start, end) = (self.char_index(start), self.char_index(end));
+        self.rope.remove(start..end);
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W, format: Format) -> io::Result<usize> {
+        let mut written = 0;
+        if format.bom {
+            let bom = format.encoding.bom();
+            writer.write_all(bom)?;
+            written += bom.len();
+        }
+        let mut end = self.rope.len_chars();
+        if !format.final_newline && end > 0 && self.rope.char(end - 1) == '\n' {
+            end -= 1;
+        }
+        let ending = format.line_ending.as_str();
+        for line in self.rope.slice(..end).lines() {
+            let mut len = line.len_chars();
+            let newline = len > 0 && line.char(len - 1) == '\n';
+            if newline {
+                len -= 1;
+                if !format.keep_cr && len > 0 && line.char(len - 1) == '\r' {
+                    len -= 1;
+                }
+            }
+            for chunk in line.slice(..len).chunks() {
+                written += format.encoding.write(writer, chunk)?;
+            }
+            if newline {
+                written += format.encoding.write(writer, ending)?;
+            }
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding;
+    use super::{LineEnding, Text};
+
+    fn round_trip(bytes: &[u8]) -> Vec<u8> {
+        let (text, format) = Text::from_decoded(encoding::decode(bytes.to_vec()));
+        let mut written = Vec::new();
+        text.write_to(&mut written, format).unwrap();
+        written
+    }
+
+    // Saved after `:set fileformat=` picked `ending`.
+    fn converted(bytes: &[u8], ending: LineEnding) -> Vec<u8> {
+        let (text, mut format) = Text::from_decoded(encoding::decode(bytes.to_vec()));
+        format.line_ending = ending;
+        format.keep_cr = false;
+        let mut written = Vec::new();
+        text.write_to(&mut written, format).unwrap();
+        written
+    }
+
+    #[test]
+    fn mixed_line_endings_convert() {
+        let mixed = b"a\r\nb\nc\r\n";
+        assert_eq!(converted(mixed, LineEnding::Crlf), b"a\r\nb\r\nc\r\n".to_vec());
+        assert_eq!(converted(mixed, LineEnding::Lf), b"a\nb\nc\n".to_vec());
+        assert_eq!(converted(b"a\r\nb\nc", LineEnding::Crlf), b"a\r\nb\r\nc".to_vec());
+    }
+
+    #[test]
+    fn mixed_line_endings_round_trip() {
+        for bytes in &[&b"a\r\nb\r\nc\nd\r\n"[..], b"a\rb\rc\r\nd\r", b"a\nb\r\nc\rd"] {
+            assert_eq!(round_trip(bytes), bytes.to_vec());
+        }
+    }
+
+    #[test]
+    fn binary_files_round_trip() {
+        for bytes in &[&b"\xff\x00a\r\nb\r\nc\n"[..], b"\x00\ra\rb\r"] {
+            assert_eq!(round_trip(bytes), bytes.to_vec());
+        }
+    }
+
+    #[test]
+    fn single_line_endings_round_trip() {
+        for bytes in &[&b"a\nb\n"[..], b"a\r\nb\r\n", b"a\rb\r", b"a\r\nb"] {
+            assert_eq!(round_trip(bytes), bytes.to_vec());
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use toml::value::{Table, Value};
+use config;
+use error::{self, Error};
+
+const DEFAULT_THEME: &str = include_str!("../themes/default.toml");
+
+// What the terminal can show, from `COLORTERM` and `TERM`.
+#[derive(Copy, Clone, PartialEq)]
+pub enum ColorDepth {
+    Basic,
+    Indexed,
+    TrueColor,
+}
+
+impl ColorDepth {
+    pub fn detect() -> ColorDepth {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" || term.ends_with("-direct") {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Indexed
+        } else {
+            ColorDepth::Basic
+        }
+    }
+}
+
+// The 16 basic colors as xterm shows them, for picking the closest one.
+const BASIC_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+    (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+    (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+const BASIC_NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+// Levels of the 6x6x6 color cube in the 256-color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Color {
+    Default,
+    // One of the 16 basic colors.
+    Basic(u8),
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    fn parse(value: &Value) -> Option<Color> {
+        match *value {
+            Value::Integer(index) if (0..256).contains(&index) => Some(Color::Indexed(index as u8)),
+            Value::String(ref name) => {
+                let name = name.to_ascii_lowercase();
+                if name == "default" || name == "none" {
+                    return Some(Color::Default)
+                }
+                if let Some(hex) = name.strip_prefix('#') {
+                    let channel = |i: usize| hex.get(i..i + 2).and_then(|digits| u8::from_str_radix(digits, 16).ok());
+                    return match (hex.len(), channel(0), channel(2), channel(4)) {
+                        (6, Some(r), Some(g), Some(b)) => Some(Color::Rgb(r, g, b)),
+                        _ => None,
+                    }
+                }
+                if name == "grey" || name == "gray" {
+                    return Some(Color::Basic(8))
+                }
+                let (bright, base) = match name.strip_prefix("bright-") {
+                    Some(base) => (8, base),
+                    None => (0, name.as_str()),
+                };
+                BASIC_NAMES.iter().position(|&basic| basic == base).map(|i| Color::Basic(i as u8 + bright))
+            },
+            _ => None,
+        }
+    }
+
+    // SGR parameters for this color as the foreground, or the background
+    // with `background` set, in what the terminal can show.
+    fn sgr(self, depth: ColorDepth, background: bool) -> String {
+        let (base, bright_base) = if background {(40, 100)} else {(30, 90)};
+        let extended = if background {48} else {38};
+        match (self, depth) {
+            (Color::Default, _) => (base + 9).to_string(),
+            (Color::Basic(n), _) if n < 8 => (base + n as u32).to_string(),
+            (Color::Basic(n), _) => (bright_base + n as u32 - 8).to_string(),
+            (Color::Indexed(n), ColorDepth::Basic) => Color::Basic(nearest_basic(indexed_rgb(n))).sgr(depth, background),
+            (Color::Indexed(n), _) => format!("{};5;{}", extended, n),
+            (Color::Rgb(r, g, b), ColorDepth::TrueColor) => format!("{};2;{};{};{}", extended, r, g, b),
+            (Color::Rgb(r, g, b), ColorDepth::Indexed) => format!("{};5;{}", extended, nearest_indexed((r, g, b))),
+            (Color::Rgb(r, g, b), ColorDepth::Basic) => Color::Basic(nearest_basic((r, g, b))).sgr(depth, background),
+        }
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+fn nearest_basic(rgb: (u8, u8, u8)) -> u8 {
+    (0..16).min_by_key(|&i| distance(rgb, BASIC_RGB[i as usize])).unwrap_or(0)
+}
+
+// The closest color of the cube or the grey ramp of the 256-color palette.
+fn nearest_indexed(rgb: (u8, u8, u8)) -> u8 {
+    let level = |c: u8| (0..6).min_by_key(|&i| (CUBE_LEVELS[i] as i32 - c as i32).abs()).unwrap_or(0);
+    let (r, g, b) = (level(rgb.0), level(rgb.1), level(rgb.2));
+    let cube = (16 + 36 * r + 6 * g + b) as u8;
+    let average = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let grey = (232 + (average.saturating_sub(3) / 10).min(23)) as u8;
+    if distance(rgb, indexed_rgb(grey)) < distance(rgb, indexed_rgb(cube)) {grey} else {cube}
+}
+
+fn indexed_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => BASIC_RGB[index as usize],
+        16..=231 => {
+            let i = index as usize - 16;
+            (CUBE_LEVELS[i / 36], CUBE_LEVELS[i / 6 % 6], CUBE_LEVELS[i % 6])
+        },
+        _ => {
+            let level = 8 + 10 * (index - 232);
+            (level, level, level)
+        },
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Style {
+    fg: Color,
+    bg: Color,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    reverse: bool,
+}
+
+impl Style {
+    fn new() -> Style {
+        Style { fg: Color::Default, bg: Color::Default, bold: false, italic: false, underline: false, reverse: false }
+    }
+
+    // Starts from a reset, so switching styles never leaves attributes behind.
+    fn escape(self, depth: ColorDepth) -> String {
+        let mut params = vec!["0".to_string(), self.fg.sgr(depth, false), self.bg.sgr(depth, true)];
+        for &(on, param) in &[(self.bold, "1"), (self.italic, "3"), (self.underline, "4"), (self.reverse, "7")] {
+            if on {
+                params.push(param.to_string());
+            }
+        }
+        format!("\x1b[{}m", params.join(";"))
+    }
+}
+
+// Everything a theme colors. Syntax groups come first, in the order of
+// `Highlight`.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Group {
+    Normal,
+    Number,
+    Type,
+    Keyword,
+    ImportantKeyword,
+    String,
+    Comment,
+    SearchMatch,
+    Selection,
+    StatusBar,
+    StatusBarInactive,
+    ModeNormal,
+    ModeInsert,
+    ModeVisual,
+    Gutter,
+    NonText,
+}
+
+const GROUPS: [(Group, &str); 16] = [
+    (Group::Normal, "normal"),
+    (Group::Number, "number"),
+    (Group::Type, "type"),
+    (Group::Keyword, "keyword"),
+    (Group::ImportantKeyword, "important_keyword"),
+    (Group::String, "string"),
+    (Group::Comment, "comment"),
+    (Group::SearchMatch, "search_match"),
+    (Group::Selection, "selection"),
+    (Group::StatusBar, "status_bar"),
+    (Group::StatusBarInactive, "status_bar_inactive"),
+    (Group::ModeNormal, "mode_normal"),
+    (Group::ModeInsert, "mode_insert"),
+    (Group::ModeVisual, "mode_visual"),
+    (Group::Gutter, "gutter"),
+    (Group::NonText, "non_text"),
+];
+
+// Styles for every group, with their escape sequences worked out for the
+// terminal up front.
+pub struct Theme {
+    styles: Vec<Style>,
+    escapes: Vec<String>,
+}
+
+impl Theme {
+    pub fn new() -> Theme {
+        let default = parse(DEFAULT_THEME, vec![Style::new(); GROUPS.len()]);
+        Theme::with_styles(default.expect("the built-in theme is valid"))
+    }
+
+    // Groups take the colors of `normal` that they leave unset.
+    fn with_styles(styles: Vec<Style>) -> Theme {
+        let depth = ColorDepth::detect();
+        let normal = styles[Group::Normal as usize];
+        let inherit = |color: Color, base: Color| if color == Color::Default {base} else {color};
+        let escapes = styles.iter()
+            .map(|&style| Style { fg: inherit(style.fg, normal.fg), bg: inherit(style.bg, normal.bg), ..style }.escape(depth))
+            .collect();
+        Theme { styles, escapes }
+    }
+
+    // The theme `name` from a `kilo-rs/themes/{name}.toml` file in the
+    // config or data directories, or the built-in `default`. Groups it
+    // leaves out keep their default style.
+    pub fn load(name: &str) -> error::Result<Theme> {
+        let default = Theme::new();
+        for path in theme_paths(name) {
+            match fs::read_to_string(&path) {
+                Ok(contents) => return parse(&contents, default.styles).map(Theme::with_styles),
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+                Err(err) => return Err(Error::Io(err)),
+            }
+        }
+        if name == "default" {
+            Ok(default)
+        } else {
+            Err(Error::Config(format!("no theme named \"{}\"", name)))
+        }
+    }
+
+    pub fn escape(&self, group: Group) -> &str {
+        &self.escapes[group as usize]
+    }
+
+}
+
+fn theme_paths(name: &str) -> Vec<PathBuf> {
+    let file = format!("{}.toml", name);
+    config::config_dir().into_iter()
+        .chain(config::data_dirs())
+        .map(|dir| dir.join("themes").join(&file))
+        .collect()
+}
+
+// Reads a theme over `styles`. Errors name the group and field at fault.
+fn parse(contents: &str, mut styles: Vec<Style>) -> error::Result<Vec<Style>> {
+    let table = match contents.parse::<Value>()? {
+        Value::Table(table) => table,
+        _ => return Err(Error::Config("expected a table".to_string())),
+    };
+    for (name, value) in table {
+        let group = match GROUPS.iter().find(|&&(_, group)| group == name) {
+            Some(&(group, _)) => group,
+            None => return Err(Error::Config(format!("unknown group `{}`", name))),
+        };
+        let fields = value.as_table().ok_or_else(|| Error::Config(format!("`{}` must be a table", name)))?;
+        styles[group as usize] = parse_style(&name, fields)?;
+    }
+    Ok(styles)
+}
+
+fn parse_style(group: &str, fields: &Table) -> error::Result<Style> {
+    let mut style = Style::new();
+    for (field, value) in fields {
+        let invalid = || Error::Config(format!("invalid `{}.{}`: {}", group, field, value));
+        match field.as_str() {
+            "fg" => style.fg = Color::parse(value).ok_or_else(invalid)?,
+            "bg" => style.bg = Color::parse(value).ok_or_else(invalid)?,
+            "bold" => style.bold = value.as_bool().ok_or_else(invalid)?,
+            "italic" => style.italic = value.as_bool().ok_or_else(invalid)?,
+            "underline" => style.underline = value.as_bool().ok_or_else(invalid)?,
+            "reverse" => style.reverse = value.as_bool().ok_or_else(invalid)?,
+            _ => return Err(Error::Config(format!("unknown field `{}.{}`", group, field))),
+        }
+    }
+    Ok(style)
+}
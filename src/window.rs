@@ -0,0 +1,344 @@
+use std::mem;
+use termion::event::Key;
+use termion::terminal_size;
+use Editor;
+use Mode;
+
+// Screen area in cells. A window's area includes its status line.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Rect {
+    pub top: usize,
+    pub left: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// Cursor and scroll position of a window into its buffer.
+#[derive(Copy, Clone)]
+pub struct View {
+    pub cx: usize,
+    pub cy: usize,
+    pub rowoff: usize,
+    // With wrapping, the column the top row is shown from.
+    pub coloff: usize,
+}
+
+// The active window's view lives in its buffer, the others keep their own.
+pub struct Window {
+    pub buffer: usize,
+    pub view: View,
+}
+
+pub enum Layout {
+    Window(usize),
+    // Vertical splits put `first` left of `second`, horizontal ones above it.
+    Split {
+        vertical: bool,
+        ratio: f32,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    // Areas of all windows in tree order, plus the vertical borders between them.
+    pub fn rects(&self, area: Rect, windows: &mut Vec<(usize, Rect)>, borders: &mut Vec<Rect>) {
+        match *self {
+            Layout::Window(id) => windows.push((id, area)),
+            Layout::Split { vertical, ratio, ref first, ref second } => {
+                let (a, border, b) = split_area(area, vertical, ratio);
+                first.rects(a, windows, borders);
+                borders.extend(border);
+                second.rects(b, windows, borders);
+            },
+        }
+    }
+
+    fn is_window(&self, id: usize) -> bool {
+        matches!(*self, Layout::Window(window) if window == id)
+    }
+
+    fn contains(&self, id: usize) -> bool {
+        match *self {
+            Layout::Window(window) => window == id,
+            Layout::Split { ref first, ref second, .. } => first.contains(id) || second.contains(id),
+        }
+    }
+
+    // Splits window `id`, putting `new` above or left of it.
+    pub fn split(&mut self, id: usize, new: usize, vertical: bool) -> bool {
+        match *self {
+            Layout::Window(window) if window == id => {
+                *self = Layout::Split {
+                    vertical,
+                    ratio: 0.5,
+                    first: Box::new(Layout::Window(new)),
+                    second: Box::new(Layout::Window(id)),
+                };
+                true
+            },
+            Layout::Window(_) => false,
+            Layout::Split { ref mut first, ref mut second, .. } => first.split(id, new, vertical) || second.split(id, new, vertical),
+        }
+    }
+
+    // Removes window `id`, its sibling takes over the space, and renumbers
+    // the windows after it so ids keep matching positions in the window list.
+    pub fn remove(&mut self, id: usize) -> bool {
+        if !self.remove_window(id) {
+            return false
+        }
+        self.renumber(id);
+        true
+    }
+
+    fn remove_window(&mut self, id: usize) -> bool {
+        let sibling = match *self {
+            Layout::Window(_) => return false,
+            Layout::Split { ref mut first, ref mut second, .. } => {
+                if first.is_window(id) {
+                    mem::replace(&mut **second, Layout::Window(0))
+                } else if second.is_window(id) {
+                    mem::replace(&mut **first, Layout::Window(0))
+                } else {
+                    return first.remove_window(id) || second.remove_window(id)
+                }
+            },
+        };
+        *self = sibling;
+        true
+    }
+
+    fn renumber(&mut self, removed: usize) {
+        match *self {
+            Layout::Window(ref mut id) => if *id > removed {*id -= 1},
+            Layout::Split { ref mut first, ref mut second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            },
+        }
+    }
+
+    // Grows window `id` by `delta` cells (shrinks when negative) along the
+    // innermost split of the given direction around it.
+    pub fn resize(&mut self, id: usize, area: Rect, vertical: bool, delta: isize) -> bool {
+        match *self {
+            Layout::Window(_) => false,
+            Layout::Split { vertical: split_vertical, ref mut ratio, ref mut first, ref mut second } => {
+                let (a, _, b) = split_area(area, split_vertical, *ratio);
+                let in_first = first.contains(id);
+                if !in_first && !second.contains(id) {
+                    return false
+                }
+                let resized = if in_first {
+                    first.resize(id, a, vertical, delta)
+                } else {
+                    second.resize(id, b, vertical, delta)
+                };
+                if resized || split_vertical != vertical {
+                    return resized
+                }
+                let (size, usable, min) = if vertical {
+                    (a.width as isize, area.width as isize - 1, 1)
+                } else {
+                    (a.height as isize, area.height as isize, 2)
+                };
+                let size = if in_first {size + delta} else {size - delta};
+                let size = size.max(min).min(usable - min);
+                *ratio = size as f32 / usable as f32;
+                true
+            },
+        }
+    }
+}
+
+// Scroll offsets that bring row `cy`, column `rx` into view in a window of
+// `rect`'s size, moving as little as possible from `offsets`.
+pub fn scroll_into_view(offsets: (usize, usize), cy: usize, rx: usize, rect: Rect) -> (usize, usize) {
+    let (mut rowoff, mut coloff) = offsets;
+    let (rows, cols) = (rect.height.saturating_sub(1).max(1), rect.width.max(1));
+    if cy < rowoff {
+        rowoff = cy;
+    }
+    if cy >= rowoff + rows {
+        rowoff = cy - rows + 1;
+    }
+    if rx < coloff {
+        coloff = rx;
+    }
+    if rx >= coloff + cols {
+        coloff = rx - cols + 1;
+    }
+    (rowoff, coloff)
+}
+
+// Divides `area` into the two halves of a split and the border column
+// between vertical halves. Each half keeps at least one text row and its
+// status line, or one column, while the area has room for it; neither
+// grows past the area.
+fn split_area(area: Rect, vertical: bool, ratio: f32) -> (Rect, Option<Rect>, Rect) {
+    if vertical {
+        let usable = area.width.saturating_sub(1);
+        let width = ((usable as f32 * ratio).round() as usize).max(1).min(usable.saturating_sub(1).max(1)).min(usable);
+        let first = Rect { width, ..area };
+        let border = Rect { left: area.left + width, width: 1, ..area };
+        let second = Rect { left: area.left + width + 1, width: usable.saturating_sub(width), ..area };
+        (first, Some(border), second)
+    } else {
+        let height = ((area.height as f32 * ratio).round() as usize).max(2).min(area.height.saturating_sub(2).max(2)).min(area.height);
+        let first = Rect { height, ..area };
+        let second = Rect { top: area.top + height, height: area.height.saturating_sub(height), ..area };
+        (first, None, second)
+    }
+}
+
+impl Editor {
+    fn screen_area(&self) -> Rect {
+        Rect { top: 0, left: 0, width: self.screencols as usize, height: self.screenrows as usize }
+    }
+
+    pub fn window_rects(&self) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        let mut windows = Vec::new();
+        let mut borders = Vec::new();
+        self.layout.rects(self.screen_area(), &mut windows, &mut borders);
+        (windows, borders)
+    }
+
+    // Picks up a new terminal size. The windows keep their share of the
+    // screen and their cursors in view, and everything is redrawn at once.
+    pub fn resize(&mut self) {
+        let (cols, rows) = match terminal_size() {
+            Ok(size) => size,
+            Err(_) => return,
+        };
+        self.screencols = cols;
+        self.screenrows = rows.saturating_sub(1);
+        self.draw();
+    }
+
+    // Keeps the views of the other windows inside their buffers, which may
+    // have been edited in the active window, and their cursors in view.
+    pub fn scroll_windows(&mut self) {
+        let (windows, _) = self.window_rects();
+        for (id, rect) in windows {
+            if id == self.active {continue}
+            let buffer = &mut self.buffers[self.windows[id].buffer];
+            let view = buffer.clamp_view(self.windows[id].view);
+            let (rowoff, coloff) = buffer.scroll_view(view, rect);
+            self.windows[id].view = View { rowoff, coloff, ..view };
+        }
+    }
+
+    pub fn window_rect(&self, id: usize) -> Rect {
+        let (windows, _) = self.window_rects();
+        windows.into_iter().find(|&(window, _)| window == id).map_or(self.screen_area(), |(_, rect)| rect)
+    }
+
+    // The key following Ctrl-W in normal mode.
+    pub fn window_command(&mut self, c: Key) {
+        let result = match c {
+            Key::Char('s') | Key::Char('S') | Key::Ctrl('s') => self.split_window(false),
+            Key::Char('v') | Key::Ctrl('v') => self.split_window(true),
+            Key::Char('w') | Key::Ctrl('w') => return self.cycle_window(true),
+            Key::Char('W') => return self.cycle_window(false),
+            Key::Char('h') | Key::Left | Key::Ctrl('h') => return self.focus_direction(Key::Left),
+            Key::Char('j') | Key::Down | Key::Ctrl('j') => return self.focus_direction(Key::Down),
+            Key::Char('k') | Key::Up | Key::Ctrl('k') => return self.focus_direction(Key::Up),
+            Key::Char('l') | Key::Right | Key::Ctrl('l') => return self.focus_direction(Key::Right),
+            Key::Char('c') | Key::Char('q') => self.close_window(),
+            Key::Char('o') => return self.only_window(),
+            Key::Char('+') => return self.resize_window(false, 1),
+            Key::Char('-') => return self.resize_window(false, -1),
+            Key::Char('>') => return self.resize_window(true, 1),
+            Key::Char('<') => return self.resize_window(true, -1),
+            _ => return,
+        };
+        if let Err(message) = result {
+            self.set_status_message(message);
+        }
+    }
+
+    // The new window shows the same buffer and view and becomes active.
+    pub fn split_window(&mut self, vertical: bool) -> Result<(), String> {
+        let rect = self.window_rect(self.active);
+        if (vertical && rect.width < 3) || (!vertical && rect.height < 4) {
+            return Err("Not enough room".to_string())
+        }
+        let id = self.windows.len();
+        let view = self.buffer().view();
+        self.windows.push(Window { buffer: self.current(), view });
+        self.layout.split(self.active, id, vertical);
+        self.focus_window(id);
+        Ok(())
+    }
+
+    pub fn close_window(&mut self) -> Result<(), String> {
+        if self.windows.len() == 1 {
+            return Err("Cannot close last window".to_string())
+        }
+        let (windows, _) = self.window_rects();
+        let order = windows.iter().position(|&(id, _)| id == self.active).unwrap_or(0);
+        let closed = self.active;
+        self.layout.remove(closed);
+        self.windows.remove(closed);
+        let (windows, _) = self.window_rects();
+        self.active = windows[order.min(windows.len() - 1)].0;
+        let view = self.windows[self.active].view;
+        self.buffer_mut().set_view(view);
+        self.mode = Mode::Normal;
+        Ok(())
+    }
+
+    pub fn only_window(&mut self) {
+        let window = self.windows.swap_remove(self.active);
+        self.windows = vec![window];
+        self.layout = Layout::Window(0);
+        self.active = 0;
+    }
+
+    fn focus_window(&mut self, id: usize) {
+        if id == self.active {return}
+        let view = self.buffer().view();
+        self.windows[self.active].view = view;
+        self.active = id;
+        let view = self.windows[id].view;
+        self.buffer_mut().set_view(view);
+        self.mode = Mode::Normal;
+        self.pending_key = None;
+    }
+
+    fn cycle_window(&mut self, forward: bool) {
+        let (windows, _) = self.window_rects();
+        let order = windows.iter().position(|&(id, _)| id == self.active).unwrap_or(0);
+        let next = (if forward {order + 1} else {order + windows.len() - 1}) % windows.len();
+        self.focus_window(windows[next].0);
+    }
+
+    // Moves to the window next to the cursor in the given direction.
+    fn focus_direction(&mut self, direction: Key) {
+        let rect = self.window_rect(self.active);
+        let current = self.buffer_mut();
+        let text = current.text_rect(rect);
+        let (y, x) = current.cursor_on_screen(text);
+        let (row, col) = (text.top + y, text.left + x);
+        let target = match direction {
+            Key::Left if rect.left >= 2 => (rect.left - 2, row),
+            Key::Right => (rect.left + rect.width + 1, row),
+            Key::Up if rect.top >= 1 => (col, rect.top - 1),
+            Key::Down => (col, rect.top + rect.height),
+            _ => return,
+        };
+        let (windows, _) = self.window_rects();
+        let found = windows.into_iter().find(|&(_, r)| {
+            target.0 >= r.left && target.0 < r.left + r.width && target.1 >= r.top && target.1 < r.top + r.height
+        });
+        if let Some((id, _)) = found {
+            self.focus_window(id);
+        }
+    }
+
+    fn resize_window(&mut self, vertical: bool, delta: isize) {
+        let area = self.screen_area();
+        self.layout.resize(self.active, area, vertical, delta);
+    }
+}
@@ -0,0 +1,207 @@
+use termion::event::Key;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+use buffer::Buffer;
+use window::{self, Rect, View};
+use Row;
+
+// Shown at the start of the screen lines a wrapped row continues on.
+pub const KILO_SHOWBREAK: &str = "\u{21aa}";
+
+impl Row {
+    // Columns at which the screen lines of the row start when it is wrapped
+    // at `width`. Continuation lines are `indent` narrower to leave room for
+    // the wrap indicator. With `linebreak` set rows are broken after the last
+    // blank that fits rather than inside a word.
+    fn wrap(&self, width: usize, indent: usize, linebreak: bool) -> Vec<usize> {
+        let mut starts = vec![0];
+        let mut blank = None;
+        let mut col = 0;
+        for grapheme in self.render.graphemes(true) {
+            let width_here = grapheme.width();
+            loop {
+                let start = starts[starts.len() - 1];
+                let room = if starts.len() == 1 {width} else {width.saturating_sub(indent)}.max(1);
+                if col + width_here <= start + room || col == start {break}
+                starts.push(match blank {
+                    Some(after) if linebreak && after > start => after,
+                    _ => col,
+                });
+                blank = None;
+            }
+            col += width_here;
+            // Tabs are already spaces in `render`.
+            if grapheme == " " {
+                blank = Some(col);
+            }
+        }
+        starts
+    }
+}
+
+impl Buffer {
+    // Columns the wrap indicator takes in a text area `width` columns wide.
+    // It is left out when it would leave no room for text.
+    pub fn showbreak_width(&self, width: usize) -> usize {
+        let indent = self.showbreak.width();
+        if indent < width {indent} else {0}
+    }
+
+    // Start columns of the screen lines row `y` takes in a text area
+    // `width` columns wide, just one when it is not wrapped.
+    pub fn screen_lines(&mut self, y: usize, width: usize) -> Vec<usize> {
+        if !self.wrap || y >= self.text.len() {
+            return vec![0]
+        }
+        let (indent, linebreak) = (self.showbreak_width(width), self.linebreak);
+        self.row(y).wrap(width, indent, linebreak)
+    }
+
+    // Which of `starts` column `rx` is on, and the screen column there.
+    fn locate(&self, starts: &[usize], rx: usize, width: usize) -> (usize, usize) {
+        let line = starts.iter().rposition(|&start| start <= rx).unwrap_or(0);
+        let indent = if line > 0 {self.showbreak_width(width)} else {0};
+        (line, (indent + rx - starts[line]).min(width.saturating_sub(1)))
+    }
+
+    fn cursor_rx(&self, view: View) -> usize {
+        if view.cy < self.text.len() {self.row_cx_to_rx(view.cy, view.cx)} else {0}
+    }
+
+    // Scroll offsets that bring the cursor of `view` into a window of
+    // `rect`. Wrapped rows never scroll sideways, and scroll down by whole
+    // rows once the cursor's screen line would fall off the bottom. Their
+    // column offset is the column the top row is shown from instead, for
+    // rows with more screen lines than the window has.
+    pub fn scroll_view(&mut self, view: View, rect: Rect) -> (usize, usize) {
+        let rect = self.text_rect(rect);
+        let rx = self.cursor_rx(view);
+        if !self.wrap {
+            return window::scroll_into_view((view.rowoff, view.coloff), view.cy, rx, rect)
+        }
+        let rows = rect.height.saturating_sub(1).max(1);
+        // Every row takes at least one screen line, so rows further up than
+        // the window is high can't be in it.
+        let mut rowoff = view.rowoff.min(view.cy).max(view.cy.saturating_sub(rows - 1));
+        let starts = self.screen_lines(view.cy, rect.width);
+        let (line, _) = self.locate(&starts, rx, rect.width);
+        // Screen lines of the top row scrolled off the window.
+        let mut skip = if rowoff == view.rowoff {self.skipped_lines(view, rect.width)} else {0};
+        if rowoff == view.cy {
+            skip = skip.min(line);
+        }
+        let mut above: Vec<usize> = (rowoff..view.cy).map(|y| self.screen_lines(y, rect.width).len()).collect();
+        let mut used = above.iter().sum::<usize>() + line + 1 - skip;
+        while used > rows && rowoff < view.cy {
+            used -= above.remove(0) - skip;
+            skip = 0;
+            rowoff += 1;
+        }
+        skip += used.saturating_sub(rows);
+        let coloff = if skip == 0 {0} else {self.screen_lines(rowoff, rect.width)[skip]};
+        (rowoff, coloff)
+    }
+
+    // How many screen lines of its top row a wrapped `view` leaves out.
+    pub fn skipped_lines(&mut self, view: View, width: usize) -> usize {
+        if view.coloff == 0 {return 0}
+        let starts = self.screen_lines(view.rowoff, width);
+        starts.iter().rposition(|&start| start <= view.coloff).unwrap_or(0)
+    }
+
+    // Where the cursor is drawn in the active window's text area `rect`,
+    // which has a status line below it.
+    pub fn cursor_on_screen(&mut self, rect: Rect) -> (usize, usize) {
+        let view = self.view();
+        let rx = self.cursor_rx(view);
+        let (y, x) = if !self.wrap {
+            (view.cy.saturating_sub(view.rowoff), rx.saturating_sub(view.coloff))
+        } else {
+            let width = rect.width;
+            let above: usize = (view.rowoff..view.cy).map(|y| self.screen_lines(y, width).len()).sum();
+            let starts = self.screen_lines(view.cy, width);
+            let (line, col) = self.locate(&starts, rx, width);
+            ((above + line).saturating_sub(self.skipped_lines(view, width)), col)
+        };
+        (y.min(rect.height.saturating_sub(2)), x.min(rect.width.saturating_sub(1)))
+    }
+
+    // Up and Down in a wrapped row go to the screen line above or below,
+    // keeping the screen column. Returns false when there is none, leaving
+    // the move to row by row movement.
+    pub fn move_screen_line(&mut self, key: Key) -> bool {
+        let width = self.wrap_width;
+        if !self.wrap || width == 0 || self.cy >= self.text.len() {
+            return false
+        }
+        let starts = self.screen_lines(self.cy, width);
+        let (line, col) = self.locate(&starts, self.row_cx_to_rx(self.cy, self.cx), width);
+        let (y, target) = match key {
+            Key::Down if line + 1 < starts.len() => (self.cy, Some(line + 1)),
+            Key::Down if self.cy + 1 < self.text.len() => (self.cy + 1, Some(0)),
+            Key::Up if line > 0 => (self.cy, Some(line - 1)),
+            Key::Up if self.cy > 0 => (self.cy - 1, None),
+            _ => return false,
+        };
+        let starts = self.screen_lines(y, width);
+        let target = target.unwrap_or(starts.len() - 1);
+        let indent = if target > 0 {self.showbreak_width(width)} else {0};
+        let mut rx = starts[target] + col.saturating_sub(indent);
+        if let Some(&next) = starts.get(target + 1) {
+            rx = rx.min(next - 1);
+        }
+        self.cy = y;
+        self.cx = self.row_rx_to_cx(y, rx);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::Buffer;
+    use encoding;
+    use text::Text;
+    use window::{Rect, View};
+
+    // A wrapped buffer whose rows are `rows` characters long.
+    fn wrapped(rows: &[usize]) -> Buffer {
+        let content: String = rows.iter().map(|&len| format!("{}\n", "x".repeat(len))).collect();
+        let mut buffer = Buffer::new();
+        buffer.text = Text::from_decoded(encoding::decode(content.into_bytes())).0;
+        buffer.wrap = true;
+        buffer.showbreak = String::new();
+        buffer
+    }
+
+    #[test]
+    fn rows_taller_than_the_window_scroll_within_the_row() {
+        // Four screen lines of ten columns, in a window with three.
+        let mut buffer = wrapped(&[40]);
+        let rect = Rect { top: 0, left: 0, width: 10, height: 4 };
+        let view = View { cx: 35, cy: 0, rowoff: 0, coloff: 0 };
+        assert_eq!(buffer.scroll_view(view, rect), (0, 10));
+        buffer.set_view(View { coloff: 10, ..view });
+        assert_eq!(buffer.cursor_on_screen(rect), (2, 5));
+        // Going back up shows the start of the row again.
+        let view = View { cx: 3, cy: 0, rowoff: 0, coloff: 10 };
+        assert_eq!(buffer.scroll_view(view, rect), (0, 0));
+    }
+
+    #[test]
+    fn scrolling_onto_a_tall_row_skips_the_rows_above() {
+        let mut buffer = wrapped(&[5, 40]);
+        let rect = Rect { top: 0, left: 0, width: 10, height: 4 };
+        let view = View { cx: 39, cy: 1, rowoff: 0, coloff: 0 };
+        assert_eq!(buffer.scroll_view(view, rect), (1, 10));
+        let view = View { cx: 0, cy: 1, rowoff: 0, coloff: 0 };
+        assert_eq!(buffer.scroll_view(view, rect), (0, 0));
+    }
+
+    #[test]
+    fn cursor_stays_inside_the_text_area() {
+        let mut buffer = wrapped(&[40]);
+        buffer.set_view(View { cx: 35, cy: 0, rowoff: 0, coloff: 0 });
+        let rect = Rect { top: 0, left: 0, width: 10, height: 4 };
+        assert_eq!(buffer.cursor_on_screen(rect), (2, 5));
+    }
+}